@@ -1,6 +1,8 @@
 // use crate::coreio::ensure_dir_exists;
 // use crate::errors::Error;
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use crate::out::{Format, DIFF_CONTEXT};
+use crate::ram::{DigestAlgorithm, Region, SpellKind, Transform};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -8,12 +10,19 @@ use clap::{Args, Parser, Subcommand};
 pub struct Engine {
     #[command(subcommand)]
     pub commands: Commands,
+    #[arg(long, global = true, env = "MS_JOURNAL", help = "records every change to files in this directory so that undo can revert it")]
+    pub journal: Option<String>,
+    #[arg(long, global = true, help = "performs the changes in memory only, printing a preview instead of writing")]
+    pub dry_run: bool,
 }
 
 impl Engine {
     pub fn start() -> Commands {
         Self::parse().commands
     }
+    pub fn load() -> Engine {
+        Self::parse()
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -32,16 +41,22 @@ pub enum Commands {
 
     #[command(
         arg_required_else_help(true),
-        about = "grafts start of file with given bytes"
+        about = "grafts start of file with given bytes (e.g.: 0x100 u16le:0x1234 i64be:-1 s:\"PK\" h:deadbeef)"
     )]
     Gp(GpOps),
 
     #[command(
         arg_required_else_help(true),
-        about = "grafts end of file with given bytes"
+        about = "grafts end of file with given bytes (e.g.: 0x100 u16le:0x1234 i64be:-1 s:\"PK\" h:deadbeef)"
     )]
     Gs(GsOps),
 
+    #[command(
+        arg_required_else_help(true),
+        about = "grafts back the bytes saved by ds or de with --save-to"
+    )]
+    Graft(GraftOps),
+
     #[command(
         arg_required_else_help(true),
         about = "dels first N bytes of file"
@@ -65,20 +80,118 @@ pub enum Commands {
         about = "reads last N bytes of file"
     )]
     Re(ReOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "shows a hexdump of a file, highlighting the header of enchanted files"
+    )]
+    Dump(DumpOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "shows offset, length and value of each field in the header of an enchanted file"
+    )]
+    Layout(LayoutOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "tells the type of a file from its magic numbers, even when enchanted"
+    )]
+    What(WhatOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "lists the original type and name recorded in enchanted files"
+    )]
+    Ls(LsOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "replaces the signature of a file with the signature of another type"
+    )]
+    Swap(SwapOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "puts back the signature replaced by swap"
+    )]
+    Unswap(UnswapOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "tells how many enchantments wrap each file"
+    )]
+    Layers(LayersOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "changes, adds or removes a magic that can restore an enchanted file"
+    )]
+    Rekey(RekeyOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "applies a chain of reversible transforms to ranges of a file"
+    )]
+    Xform(XformOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "applies the operations of a JSON recipe in memory, writing the file once"
+    )]
+    Apply(ApplyOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "shows where two files differ, or the ms command turning one into the other"
+    )]
+    Diff(DiffOps),
+
+    #[command(about = "reverts the last changes recorded in the journal")]
+    Undo(UndoOps),
+
+    #[command(
+        arg_required_else_help(true),
+        about = "lists the changes to a file recorded in the journal"
+    )]
+    History(HistoryOps),
 }
 
 #[derive(Args, Debug)]
 pub struct EOps {
-    #[arg(short, long)]
-    pub magic: String,
+    #[arg(short, long, required = true, help = "repeat to let each magic restore the file on its own")]
+    pub magic: Vec<String>,
     pub filename: String,
+    #[arg(long, help = "records the name of the file in the header")]
+    pub keep_name: bool,
+    #[arg(short, long, value_enum, default_value_t)]
+    pub digest: DigestAlgorithm,
+    #[arg(short, long, value_enum, default_value_t, help = "how the scrambled bytes are scrambled, recorded so that r picks its inverse")]
+    pub spell: SpellKind,
+    #[arg(long, help = "authenticates the enchanted file with an HMAC-SHA256 keyed from a content key only the magic unwraps")]
+    pub mac: bool,
+    #[arg(long, value_name = "START:LEN", help = "scrambles LEN bytes from START instead of the first 32 bytes")]
+    pub range: Option<Region>,
+    #[arg(long, value_name = "N", conflicts_with = "range", help = "scrambles the last N bytes instead of the first 32 bytes")]
+    pub tail: Option<usize>,
+    #[arg(long, help = "keeps the length of the file, writing the header to FILENAME.ms")]
+    pub inplace: bool,
+    #[arg(long, value_name = "START:LEN", requires = "inplace", help = "writes the header to this all zeros region of the file instead")]
+    pub reserved: Option<Region>,
 }
 
 #[derive(Args, Debug)]
+#[command(group(ArgGroup::new("magics").required(true).args(["magic", "magic_file"])))]
 pub struct ROps {
     #[arg(short, long)]
-    pub magic: String,
+    pub magic: Option<String>,
     pub filename: String,
+    #[arg(long, help = "restores every layer, reusing the last magic for the innermost ones")]
+    pub all: bool,
+    #[arg(long, requires = "all", help = "file with one magic per line, outermost layer first")]
+    pub magic_file: Option<String>,
+    #[arg(long, value_name = "START:LEN", conflicts_with = "all", help = "reads the header of a file enchanted in place from this region")]
+    pub reserved: Option<Region>,
 }
 
 #[derive(Args, Debug)]
@@ -93,26 +206,143 @@ pub struct GsOps {
     pub bytes: Vec<String>,
 }
 
+#[derive(Args, Debug)]
+pub struct GraftOps {
+    pub filename: String,
+    #[arg(long, help = "file written by ds or de with --save-to")]
+    pub from: String,
+}
+
 #[derive(Args, Debug)]
 pub struct DsOps {
     pub filename: String,
     pub amount: usize,
+    #[arg(long, help = "saves the deleted bytes so that graft can put them back")]
+    pub save_to: Option<String>,
 }
 
 #[derive(Args, Debug)]
 pub struct DeOps {
     pub filename: String,
     pub amount: usize,
+    #[arg(long, help = "saves the deleted bytes so that graft can put them back")]
+    pub save_to: Option<String>,
 }
 
 #[derive(Args, Debug)]
 pub struct RsOps {
     pub filename: String,
     pub amount: usize,
+    #[arg(long, help = "reads at most the size of the file instead of failing")]
+    pub clamp: bool,
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: Format,
 }
 
 #[derive(Args, Debug)]
 pub struct ReOps {
     pub filename: String,
     pub amount: usize,
+    #[arg(long, help = "reads at most the size of the file instead of failing")]
+    pub clamp: bool,
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: Format,
+}
+
+#[derive(Args, Debug)]
+pub struct DumpOps {
+    pub filename: String,
+    #[arg(short, long, default_value_t = 0)]
+    pub offset: usize,
+    #[arg(short, long, help = "dumps at most N bytes [default: until the end of file]")]
+    pub len: Option<usize>,
+    #[arg(short, long, help = "colours the output with ANSI escape codes")]
+    pub color: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct LayoutOps {
+    pub filename: String,
+}
+
+#[derive(Args, Debug)]
+pub struct WhatOps {
+    pub filenames: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct LsOps {
+    pub filenames: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct LayersOps {
+    pub filenames: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct SwapOps {
+    pub filename: String,
+    #[arg(short, long, help = "name of the type to masquerade as, e.g.: zip, pdf, png")]
+    pub to: String,
+}
+
+#[derive(Args, Debug)]
+pub struct UnswapOps {
+    pub filename: String,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("change").required(true).args(["to", "add", "remove"])))]
+pub struct RekeyOps {
+    #[arg(short, long, help = "any magic that can currently restore the file")]
+    pub magic: String,
+    pub filename: String,
+    #[arg(long, help = "replaces the magic with this one")]
+    pub to: Option<String>,
+    #[arg(long, help = "lets this magic restore the file as well")]
+    pub add: Option<String>,
+    #[arg(long, help = "stops this magic from restoring the file")]
+    pub remove: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct XformOps {
+    pub filename: String,
+    #[arg(
+        long = "op",
+        required = true,
+        value_name = "NAME[:KEY][:START..END]",
+        help = "reverse, xor:KEY, nibbles, rotate, swap16, swap32 or shuffle:KEY, KEY being hex or any typed literal accepted by gp, applied in order"
+    )]
+    pub ops: Vec<Transform>,
+    #[arg(long, help = "reverts the transforms in the reverse order instead")]
+    pub invert: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ApplyOps {
+    pub recipe: String,
+    pub filename: String,
+    #[arg(long, help = "applies the inverse recipe, if every operation is reversible")]
+    pub invert: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffOps {
+    pub left: String,
+    pub right: String,
+    #[arg(long, default_value_t = DIFF_CONTEXT, help = "equal bytes shown around each difference")]
+    pub context: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct UndoOps {
+    #[arg(long, default_value_t = 1)]
+    pub steps: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryOps {
+    pub filename: String,
 }
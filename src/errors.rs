@@ -3,7 +3,7 @@ use std::error::Error;
 use std::fmt;
 use hex::FromHexError;
 use std::num::ParseIntError;
-use crate::ram::VecsException;
+use crate::ram::{DigestMismatch, VecsException};
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +12,21 @@ pub enum MSError {
     HexDecodingError(String),
     HexEncodingError(String),
     ParseIntError(String),
+    InvalidLiteral(String),
+    OutOfBounds(usize, usize),
+    InvalidHeader(String),
+    MagicMismatch,
+    DigestMismatch(DigestMismatch),
+    AuthenticationFailed,
+    KeySlotError(String),
+    MissingKey(String),
+    LayerFailed(usize, Box<MSError>),
+    ReservedRegion(String),
+    JournalError(String),
+    RecipeError(String),
+    UnknownFileType(String),
+    NotSwapped(String),
+    SidecarExists(String),
     VecsError(VecsException),
 }
 
@@ -27,6 +42,25 @@ impl fmt::Display for MSError {
                 VecsException::NotAllOccurrencesReplaced((pattern, occrsf, occrse)) => format!("not enought ocurrences found for {} ({}/{})", hex::encode(pattern), occrsf, occrse),
             }),
             MSError::ParseIntError(msg) => write!(f, "ParseIntError: {msg}"),
+            MSError::InvalidLiteral(msg) => write!(f, "InvalidLiteral: {msg}"),
+            MSError::InvalidHeader(msg) => write!(f, "InvalidHeader: {msg}"),
+            MSError::MagicMismatch => write!(f, "MagicMismatch: the given magic does not match the enchantment"),
+            MSError::DigestMismatch(mismatch) => write!(f, "DigestMismatch: {mismatch}"),
+            MSError::AuthenticationFailed => write!(f, "AuthenticationFailed: the header or the body of the enchanted file were modified"),
+            MSError::KeySlotError(msg) => write!(f, "KeySlotError: {msg}"),
+            MSError::MissingKey(spell) => write!(f, "MissingKey: the {spell} spell needs a key"),
+            MSError::LayerFailed(layer, e) => write!(
+                f,
+                "LayerFailed: layer {layer}: {}",
+                e.to_string().trim_start_matches("Magic Switcheroo Error")
+            ),
+            MSError::ReservedRegion(msg) => write!(f, "ReservedRegion: {msg}"),
+            MSError::JournalError(msg) => write!(f, "JournalError: {msg}"),
+            MSError::RecipeError(msg) => write!(f, "RecipeError: {msg}"),
+            MSError::UnknownFileType(what) => write!(f, "UnknownFileType: {what}"),
+            MSError::NotSwapped(filename) => write!(f, "NotSwapped: {filename} has no swap record"),
+            MSError::SidecarExists(filename) => write!(f, "SidecarExists: {filename} already exists"),
+            MSError::OutOfBounds(requested, available) => write!(f, "OutOfBounds: requested {requested} bytes but only {available} are available"),
         }
     }
 }
@@ -50,3 +84,12 @@ impl From<io::Error> for MSError {
         MSError::IOError(format!("{}", error))
     }
 }
+
+impl From<MSError> for io::Error {
+    fn from(error: MSError) -> Self {
+        match error {
+            MSError::IOError(msg) => io::Error::other(msg),
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
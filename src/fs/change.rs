@@ -0,0 +1,144 @@
+use crate::errors::MSError;
+use crate::fs::{
+    delete_end_file, delete_start_file, graft_end_file, graft_start_file, overwrite_file, write_file_atomically,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// how an operation changed a file, holding what is needed to revert it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum Change {
+    GraftStart {
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        added: Vec<u8>,
+    },
+    GraftEnd {
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        added: Vec<u8>,
+    },
+    DeleteStart {
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        removed: Vec<u8>,
+    },
+    DeleteEnd {
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        removed: Vec<u8>,
+    },
+    /// bytes were overwritten keeping the length, e.g.: by `swap` or
+    /// `xform`, holding only the span that differs
+    Overwrite {
+        offset: usize,
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        before: Vec<u8>,
+    },
+    /// the contents were rewritten as a whole, e.g.: by `r`
+    Replace {
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        before: Vec<u8>,
+    },
+    /// the file did not exist, e.g.: the record written by `swap`
+    Created,
+    /// the file was deleted, e.g.: the sidecar read by `r`
+    Removed {
+        #[cfg_attr(feature = "serde", serde(with = "hex"))]
+        before: Vec<u8>,
+    },
+}
+
+impl Change {
+    /// the smallest change turning `before` into `after`
+    pub fn between(before: &[u8], after: &[u8]) -> Change {
+        if after.len() >= before.len() && after.ends_with(before) {
+            Change::GraftStart { added: after[..after.len() - before.len()].to_vec() }
+        } else if after.starts_with(before) {
+            Change::GraftEnd { added: after[before.len()..].to_vec() }
+        } else if before.ends_with(after) {
+            Change::DeleteStart { removed: before[..before.len() - after.len()].to_vec() }
+        } else if before.starts_with(after) {
+            Change::DeleteEnd { removed: before[after.len()..].to_vec() }
+        } else if before.len() == after.len() {
+            let start = before.iter().zip(after).position(|(b, a)| b != a).unwrap_or(0);
+            let end = before.len() - before.iter().rev().zip(after.iter().rev()).position(|(b, a)| b != a).unwrap_or(0);
+            Change::Overwrite { offset: start, before: before[start..end].to_vec() }
+        } else {
+            Change::Replace { before: before.to_vec() }
+        }
+    }
+    /// the change of a file that may not exist before or after, `None`
+    /// when it was left as it was
+    pub fn between_files(before: Option<&[u8]>, after: Option<&[u8]>) -> Option<Change> {
+        match (before, after) {
+            (None, None) => None,
+            (None, Some(_)) => Some(Change::Created),
+            (Some(before), None) => Some(Change::Removed { before: before.to_vec() }),
+            (Some(before), Some(after)) if before == after => None,
+            (Some(before), Some(after)) => Some(Change::between(before, after)),
+        }
+    }
+    /// applies the inverse of this change to the file
+    pub fn revert(&self, filename: &str) -> Result<(), MSError> {
+        match self {
+            Change::GraftStart { added } => delete_start_file(filename.to_string(), added.len()).map(|_| ()),
+            Change::GraftEnd { added } => delete_end_file(filename.to_string(), added.len()).map(|_| ()),
+            Change::DeleteStart { removed } => graft_start_file(filename, removed),
+            Change::DeleteEnd { removed } => graft_end_file(filename, removed),
+            Change::Overwrite { offset, before } => overwrite_file(filename, *offset as u64, before),
+            Change::Replace { before } => write_file_atomically(filename, before),
+            Change::Created => Ok(std::fs::remove_file(filename)?),
+            Change::Removed { before } => write_file_atomically(filename, before),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_change_between() {
+        let data = [0x01, 0x02, 0x03];
+        assert_equal!(
+            Change::between(&data, &[0x00, 0x01, 0x02, 0x03]),
+            Change::GraftStart { added: vec![0x00] }
+        );
+        assert_equal!(
+            Change::between(&data, &[0x01, 0x02, 0x03, 0x04]),
+            Change::GraftEnd { added: vec![0x04] }
+        );
+        assert_equal!(
+            Change::between(&data, &[0x02, 0x03]),
+            Change::DeleteStart { removed: vec![0x01] }
+        );
+        assert_equal!(
+            Change::between(&data, &[0x01]),
+            Change::DeleteEnd { removed: vec![0x02, 0x03] }
+        );
+        assert_equal!(
+            Change::between(&data, &[0x01, 0xff, 0x03]),
+            Change::Overwrite { offset: 1, before: vec![0x02] }
+        );
+        assert_equal!(
+            Change::between(&data, &[0x03, 0x02]),
+            Change::Replace { before: data.to_vec() }
+        );
+    }
+
+    #[test]
+    fn test_change_between_files() {
+        let data = [0x01, 0x02, 0x03];
+        assert_equal!(Change::between_files(None, None), None);
+        assert_equal!(Change::between_files(Some(&data), Some(&data)), None);
+        assert_equal!(Change::between_files(None, Some(&data)), Some(Change::Created));
+        assert_equal!(
+            Change::between_files(Some(&data), None),
+            Some(Change::Removed { before: data.to_vec() })
+        );
+        assert_equal!(
+            Change::between_files(Some(&data), Some(&[0x01])),
+            Some(Change::DeleteEnd { removed: vec![0x02, 0x03] })
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::errors::MSError;
+use crate::fs::{
+    delete_end_file, delete_start_file, graft_end_file, graft_start_file, prefix_bytes, read_end_file, read_file,
+    read_start_file, suffix_bytes, write_file,
+};
+
+/// starts the files written by `ds --save-to` and `de --save-to`
+pub const CUT_MARK: &[u8] = b"MSCT";
+
+/// the end of the file some bytes were deleted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Side {
+    Start = 0,
+    End = 1,
+}
+
+impl Side {
+    pub fn from_id(id: u8) -> Option<Side> {
+        match id {
+            0 => Some(Side::Start),
+            1 => Some(Side::End),
+            _ => None,
+        }
+    }
+}
+
+/// the mark, the side and the deleted bytes in their original order
+pub fn encode_cut(side: Side, bytes: &[u8]) -> Vec<u8> {
+    let mut cut = CUT_MARK.to_vec();
+    cut.push(side as u8);
+    cut.extend(bytes);
+    cut
+}
+
+pub fn decode_cut(data: &[u8]) -> Result<(Side, Vec<u8>), MSError> {
+    let head = CUT_MARK.len() + 1;
+    if data.len() < head || &data[..CUT_MARK.len()] != CUT_MARK {
+        return Err(MSError::InvalidHeader("not a file saved by ds or de".to_string()));
+    }
+    match Side::from_id(data[CUT_MARK.len()]) {
+        Some(side) => Ok((side, data[head..].to_vec())),
+        None => Err(MSError::InvalidHeader(format!("unknown side: {}", data[CUT_MARK.len()]))),
+    }
+}
+
+/// saves the bytes deleted from `side` of a file so that `graft_file_from`
+/// can put them back
+pub fn save_cut(path: &str, side: Side, bytes: &[u8]) -> Result<(), MSError> {
+    write_file(path.to_string(), encode_cut(side, bytes))
+}
+
+/// deletes `amount` bytes from `side` of the file, returning them; when
+/// `save_to` is given they are saved there first, the file being left
+/// untouched if they cannot be
+pub fn cut_file(filename: &str, side: Side, amount: usize, save_to: Option<&str>) -> Result<Vec<u8>, MSError> {
+    if let Some(path) = save_to {
+        let bytes = match side {
+            Side::Start => read_start_file(filename.to_string(), amount, true)?,
+            Side::End => read_end_file(filename.to_string(), amount, true)?,
+        };
+        save_cut(path, side, &bytes)?;
+    }
+    match side {
+        Side::Start => delete_start_file(filename.to_string(), amount),
+        Side::End => delete_end_file(filename.to_string(), amount),
+    }
+}
+
+/// `input` with the bytes `saved` by `save_cut` put back
+pub fn graft_bytes(input: &[u8], saved: &[u8]) -> Result<Vec<u8>, MSError> {
+    let (side, bytes) = decode_cut(saved)?;
+    Ok(match side {
+        Side::Start => prefix_bytes(input, &bytes),
+        Side::End => suffix_bytes(input, &bytes),
+    })
+}
+
+/// puts the bytes saved in `path` back where they were deleted from
+pub fn graft_file_from(filename: &str, path: &str) -> Result<Side, MSError> {
+    let (saved, _) = read_file(&path.to_string())?;
+    let (side, bytes) = decode_cut(&saved)?;
+    match side {
+        Side::Start => graft_start_file(filename, &bytes)?,
+        Side::End => graft_end_file(filename, &bytes)?,
+    }
+    Ok(side)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_cut_roundtrip() -> Result<(), MSError> {
+        assert_equal!(hex::encode(encode_cut(Side::End, &[0x01, 0x02])), "4d534354010102");
+        assert_equal!(decode_cut(&encode_cut(Side::Start, &[0x01]))?, (Side::Start, vec![0x01]));
+        assert_equal!(decode_cut(b"MSCT").is_err(), true);
+        assert_equal!(decode_cut(b"MSCT\x02").is_err(), true);
+        assert_equal!(decode_cut(b"PK\x03\x04\x00").is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_graft_file_from() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("cut.bin").to_string_lossy().to_string();
+        let start = dir.path().join("start.cut").to_string_lossy().to_string();
+        let end = dir.path().join("end.cut").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..20).collect();
+
+        // Given a file with some bytes deleted from both of its ends
+        write_file(name.clone(), data.clone())?;
+        assert_equal!(cut_file(&name, Side::Start, 3, Some(&start))?, vec![0, 1, 2]);
+        cut_file(&name, Side::End, 5, Some(&end))?;
+        assert_equal!(read_file(&name)?.0, (3u8..15).collect::<Vec<u8>>());
+
+        // When I graft them back
+        assert_equal!(graft_file_from(&name, &end)?, Side::End);
+        assert_equal!(graft_file_from(&name, &start)?, Side::Start);
+
+        // Then it should have the previous contents
+        assert_equal!(read_file(&name)?.0, data);
+
+        // And grafting in memory should give the same contents
+        let middle: Vec<u8> = (3u8..15).collect();
+        let saved = read_file(&start)?.0;
+        assert_equal!(graft_bytes(&middle, &saved)?, [&data[..3], &middle[..]].concat());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cut_file_fails_to_save() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("cut.bin").to_string_lossy().to_string();
+        let missing = dir.path().join("missing").join("start.cut").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..20).collect();
+        write_file(name.clone(), data.clone())?;
+
+        // When the deleted bytes cannot be saved
+        assert_equal!(cut_file(&name, Side::Start, 3, Some(&missing)).is_err(), true);
+
+        // Then the file should be left as it was
+        assert_equal!(read_file(&name)?.0, data);
+        Ok(())
+    }
+}
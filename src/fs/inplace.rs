@@ -0,0 +1,147 @@
+use crate::errors::MSError;
+use crate::fs::{overwrite_file, read_file};
+use crate::ram::EnchantOptions;
+pub use crate::ram::{enchant_bytes_in_place, reserved_offset, restore_bytes_in_place, HeaderPlacement};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+/// appended to the name of a file enchanted in place to name its sidecar
+pub const SIDECAR_SUFFIX: &str = ".ms";
+
+pub fn sidecar_name(filename: &str) -> String {
+    format!("{filename}{SIDECAR_SUFFIX}")
+}
+
+/// creates the sidecar `filename` with `data`, failing with
+/// `MSError::SidecarExists` rather than overwriting it
+pub fn write_sidecar(filename: &str, data: &[u8]) -> Result<(), MSError> {
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(filename) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(MSError::SidecarExists(filename.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(file.write_all(data)?)
+}
+
+/// enchants the file keeping its length: only the region moved into the
+/// header is scrambled, in place, and the header is kept as `placement`
+/// says, failing with `MSError::SidecarExists` rather than overwriting the
+/// header of a previous enchantment
+pub fn enchant_file_in_place(
+    filename: String,
+    magic: String,
+    options: &EnchantOptions,
+    placement: &HeaderPlacement,
+) -> Result<(), MSError> {
+    let (read, _) = read_file(&filename)?;
+    let (output, sidecar) = enchant_bytes_in_place(&read, &magic, options, placement)?;
+    if let Some(header) = sidecar {
+        write_sidecar(&sidecar_name(&filename), &header)?;
+    }
+    overwrite_file(&filename, 0, &output)
+}
+
+/// puts back the region scrambled by `enchant_file_in_place`, removing
+/// the sidecar or zeroing the reserved region that held the header
+pub fn restore_file_in_place(filename: String, magic: String, placement: &HeaderPlacement) -> Result<(), MSError> {
+    let (scrambled, _) = read_file(&filename)?;
+    let sidecar = match placement {
+        HeaderPlacement::Sidecar => Some(read_file(&sidecar_name(&filename))?.0),
+        HeaderPlacement::Reserved(_) => None,
+    };
+    let orig = restore_bytes_in_place(&scrambled, sidecar.as_deref(), &magic, placement)?;
+    overwrite_file(&filename, 0, &orig)?;
+    if sidecar.is_some() {
+        std::fs::remove_file(sidecar_name(&filename))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{restore_file, write_file};
+    use crate::ram::{reverse_slice, Region};
+    use k9::assert_equal;
+    use std::path::Path;
+
+    fn test_data() -> Vec<u8> {
+        let mut data: Vec<u8> = (1u8..=100).collect();
+        data.extend([0x00; 156]);
+        data
+    }
+
+    #[test]
+    fn test_enchant_file_in_place_with_sidecar() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("slot.img").to_string_lossy().to_string();
+
+        // Given a file exists
+        write_file(name.clone(), test_data())?;
+
+        // When I enchant it in place
+        let options = EnchantOptions::default();
+        enchant_file_in_place(name.clone(), "THISISMAGICO".to_string(), &options, &HeaderPlacement::Sidecar)?;
+
+        // Then it should keep its length with its first bytes scrambled
+        let (scrambled, _) = read_file(&name)?;
+        assert_equal!(scrambled.len(), test_data().len());
+        assert_equal!(scrambled[..32], reverse_slice(&test_data()[..32]));
+        assert_equal!(scrambled[32..], test_data()[32..]);
+
+        // And its header should be in the sidecar
+        assert_equal!(Path::new(&sidecar_name(&name)).exists(), true);
+
+        // And enchanting it in place again should fail, keeping the sidecar
+        let (sidecar, _) = read_file(&sidecar_name(&name))?;
+        assert_equal!(
+            enchant_file_in_place(name.clone(), "OTHERMAGIC".to_string(), &options, &HeaderPlacement::Sidecar),
+            Err(MSError::SidecarExists(sidecar_name(&name)))
+        );
+        assert_equal!(read_file(&sidecar_name(&name))?.0, sidecar);
+        assert_equal!(read_file(&name)?.0, scrambled);
+
+        // When I restore it, the sidecar being found automatically
+        restore_file(name.clone(), "THISISMAGICO".to_string())?;
+
+        // Then it should have the previous contents and no sidecar
+        assert_equal!(read_file(&name)?.0, test_data());
+        assert_equal!(Path::new(&sidecar_name(&name)).exists(), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_enchant_file_in_place_with_reserved_region() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("partition.img").to_string_lossy().to_string();
+        let reserved = HeaderPlacement::Reserved(Region::Tail(128));
+
+        // Given a file with zeros at its end exists
+        write_file(name.clone(), test_data())?;
+
+        // When I enchant it in place keeping the header in its last bytes
+        let options = EnchantOptions::default();
+        enchant_file_in_place(name.clone(), "THISISMAGICO".to_string(), &options, &reserved)?;
+
+        // Then it should keep its length and have no sidecar
+        assert_equal!(read_file(&name)?.0.len(), test_data().len());
+        assert_equal!(Path::new(&sidecar_name(&name)).exists(), false);
+
+        // And a wrong magic should not restore it
+        assert_equal!(
+            restore_file_in_place(name.clone(), "NOTTHEMAGICO".to_string(), &reserved),
+            Err(MSError::MagicMismatch)
+        );
+
+        // When I restore it
+        restore_file_in_place(name.clone(), "THISISMAGICO".to_string(), &reserved)?;
+
+        // Then it should have the previous contents
+        assert_equal!(read_file(&name)?.0, test_data());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use crate::errors::MSError;
+use crate::fs::{read_file, sidecar_name, swap_record_name, write_file_atomically, Change};
+use crate::ram::crc32;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// one JSON entry per line, oldest first, within the journal directory
+pub const JOURNAL_FILENAME: &str = "journal.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: String,
+    pub command: String,
+    pub change: Change,
+    #[serde(with = "hex")]
+    pub before_crc: Vec<u8>,
+    #[serde(with = "hex")]
+    pub after_crc: Vec<u8>,
+    /// recorded by the same command as the entry before it, e.g.: for the
+    /// sidecar of the file, and reverted along with it
+    #[serde(default)]
+    pub joined: bool,
+}
+
+/// records the operations of `ms` on files so that `undo` can revert them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal {
+    dir: PathBuf,
+}
+
+/// the absolute path of an existing file, as recorded in the journal
+pub fn journal_path(filename: &str) -> Result<String, MSError> {
+    Ok(std::fs::canonicalize(filename)?.to_string_lossy().to_string())
+}
+
+/// what the entry of `command` would keep that must not outlive it: the
+/// contents before `e` and the header, with any revoked magic, before
+/// `rekey`; neither is journaled
+pub fn unjournaled(command: &str) -> Option<&'static str> {
+    match command {
+        "e" => Some("the contents before they were enchanted"),
+        "rekey" => Some("the header with the magics it replaced"),
+        _ => None,
+    }
+}
+
+/// the files an operation on `path` may create or delete next to it
+fn companions(path: &str) -> [String; 2] {
+    [sidecar_name(path), swap_record_name(path)]
+}
+
+/// the contents of the file, `None` when it does not exist
+fn snapshot(path: &str) -> Result<Option<Vec<u8>>, MSError> {
+    if Path::new(path).exists() {
+        Ok(Some(read_file(&path.to_string())?.0))
+    } else {
+        Ok(None)
+    }
+}
+
+/// the crc32 recorded for a snapshot, empty when the file does not exist
+fn snapshot_crc(snapshot: &Option<Vec<u8>>) -> Result<Vec<u8>, MSError> {
+    match snapshot {
+        Some(contents) => crc32(contents),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl Journal {
+    pub fn open(dir: &str) -> Result<Journal, MSError> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Journal { dir: PathBuf::from(dir) })
+    }
+    fn path(&self) -> PathBuf {
+        self.dir.join(JOURNAL_FILENAME)
+    }
+    pub fn entries(&self) -> Result<Vec<Entry>, MSError> {
+        if !self.path().exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for (index, line) in std::fs::read_to_string(self.path())?.lines().enumerate() {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    return Err(MSError::JournalError(format!("invalid entry at line {}: {e}", index + 1)))
+                }
+            }
+        }
+        Ok(entries)
+    }
+    pub fn append(&self, entry: &Entry) -> Result<(), MSError> {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => return Err(MSError::JournalError(format!("failed to encode entry: {e}"))),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(self.path())?;
+        Ok(writeln!(file, "{line}")?)
+    }
+    /// runs `operation` on the file, recording how it changed it and, as
+    /// joined entries, its sidecar and swap record when it created or
+    /// deleted them; fails for the commands that are `unjournaled`
+    pub fn record<T>(
+        &self,
+        filename: &str,
+        command: &str,
+        operation: impl FnOnce() -> Result<T, MSError>,
+    ) -> Result<T, MSError> {
+        if let Some(kept) = unjournaled(command) {
+            return Err(MSError::JournalError(format!(
+                "{command} is not journaled as its entry would keep {kept}"
+            )));
+        }
+        let path = journal_path(filename)?;
+        let companions = companions(&path);
+        let (before, before_crc) = read_file(&filename.to_string())?;
+        let companions_before = companions.iter().map(|c| snapshot(c)).collect::<Result<Vec<_>, _>>()?;
+        let result = operation()?;
+        let (after, after_crc) = read_file(&filename.to_string())?;
+        self.append(&Entry {
+            path,
+            command: command.to_string(),
+            change: Change::between(&before, &after),
+            before_crc,
+            after_crc,
+            joined: false,
+        })?;
+        for (companion, before) in companions.iter().zip(companions_before) {
+            let after = snapshot(companion)?;
+            if let Some(change) = Change::between_files(before.as_deref(), after.as_deref()) {
+                self.append(&Entry {
+                    path: companion.to_string(),
+                    command: command.to_string(),
+                    change,
+                    before_crc: snapshot_crc(&before)?,
+                    after_crc: snapshot_crc(&after)?,
+                    joined: true,
+                })?;
+            }
+        }
+        Ok(result)
+    }
+    /// the entries of the file, oldest first
+    pub fn history(&self, filename: &str) -> Result<Vec<Entry>, MSError> {
+        let path = journal_path(filename)?;
+        Ok(self.entries()?.into_iter().filter(|e| e.path == path).collect())
+    }
+    /// the entries of the last `steps` commands, newest first, as `undo`
+    /// reverts them
+    pub fn pending(&self, steps: usize) -> Result<Vec<Entry>, MSError> {
+        let mut pending = Vec::new();
+        let mut steps = steps;
+        for entry in self.entries()?.into_iter().rev() {
+            if steps == 0 {
+                break;
+            }
+            if !entry.joined {
+                steps -= 1;
+            }
+            pending.push(entry);
+        }
+        Ok(pending)
+    }
+    /// reverts the entries of the last `steps` commands, newest first,
+    /// stopping at the first file that changed since its entry was
+    /// recorded; returns the entries reverted
+    pub fn undo(&self, steps: usize) -> Result<Vec<Entry>, MSError> {
+        let mut entries = self.entries()?;
+        let mut undone = Vec::new();
+        for entry in self.pending(steps)? {
+            let crc = snapshot_crc(&snapshot(&entry.path)?)?;
+            if crc != entry.after_crc {
+                return Err(MSError::JournalError(format!(
+                    "{} changed since {}, expected crc {} but found {}",
+                    entry.path,
+                    entry.command,
+                    hex::encode(&entry.after_crc),
+                    hex::encode(crc)
+                )));
+            }
+            entry.change.revert(&entry.path)?;
+            let crc = snapshot_crc(&snapshot(&entry.path)?)?;
+            if crc != entry.before_crc {
+                return Err(MSError::JournalError(format!(
+                    "undoing {} left {} with crc {} instead of {}",
+                    entry.command,
+                    entry.path,
+                    hex::encode(crc),
+                    hex::encode(&entry.before_crc)
+                )));
+            }
+            entries.pop();
+            write_file_atomically(&self.path().to_string_lossy(), &encode_entries(&entries)?)?;
+            undone.push(entry);
+        }
+        Ok(undone)
+    }
+}
+
+fn encode_entries(entries: &[Entry]) -> Result<Vec<u8>, MSError> {
+    let mut encoded = Vec::new();
+    for entry in entries {
+        match serde_json::to_string(entry) {
+            Ok(line) => encoded.extend(format!("{line}\n").into_bytes()),
+            Err(e) => return Err(MSError::JournalError(format!("failed to encode entry: {e}"))),
+        }
+    }
+    Ok(encoded)
+}
+
+/// runs `operation` on the file, recording it when there is a journal
+/// unless the command is `unjournaled`
+pub fn journaled<T>(
+    journal: Option<&Journal>,
+    filename: &str,
+    command: &str,
+    operation: impl FnOnce() -> Result<T, MSError>,
+) -> Result<T, MSError> {
+    match journal {
+        Some(journal) if unjournaled(command).is_none() => journal.record(filename, command, operation),
+        _ => operation(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{
+        delete_end_file, enchant_file, enchant_file_in_place, prefix_file, rekey_file, restore_file, swap_file,
+        write_file, xform_file, HeaderPlacement, Rekey,
+    };
+    use crate::ram::{EnchantOptions, Transform};
+    use crate::sig::FileType;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_journal_undo() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::open(&dir.path().join("journal").to_string_lossy())?;
+        let name = dir.path().join("journaled.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..64).collect();
+
+        // Given a file changed by several journaled operations
+        write_file(name.clone(), data.clone())?;
+        journal.record(&name, "gp", || prefix_file(name.clone(), vec!["s:\"PK\"".to_string()]))?;
+        journal.record(&name, "de", || delete_end_file(name.clone(), 10))?;
+        let reverse = "reverse:2..6".parse::<Transform>()?;
+        journal.record(&name, "xform", || xform_file(&name, &[reverse], false))?;
+        assert_equal!(
+            journal.history(&name)?.iter().map(|e| e.command.as_str()).collect::<Vec<_>>(),
+            vec!["gp", "de", "xform"]
+        );
+        assert_equal!(
+            journal.history(&name)?[2].change,
+            Change::Overwrite { offset: 2, before: vec![0x00, 0x01, 0x02, 0x03] }
+        );
+
+        // When I undo the last two operations
+        let undone = journal.undo(2)?;
+
+        // Then the file should only keep the first one
+        assert_equal!(undone.iter().map(|e| e.command.as_str()).collect::<Vec<_>>(), vec!["xform", "de"]);
+        let mut expected = b"PK".to_vec();
+        expected.extend(&data);
+        assert_equal!(read_file(&name)?.0, expected);
+        assert_equal!(journal.entries()?.len(), 1);
+
+        // When I undo more operations than were recorded
+        assert_equal!(journal.undo(5)?.len(), 1);
+
+        // Then the file should have the previous contents
+        assert_equal!(read_file(&name)?.0, data);
+        assert_equal!(journal.entries()?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_refuses_unjournaled_commands() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::open(&dir.path().join("journal").to_string_lossy())?;
+        let name = dir.path().join("secret.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..64).collect();
+
+        // Given a file exists
+        write_file(name.clone(), data.clone())?;
+
+        // When I enchant it with a journal
+        // Then it should fail without touching the file nor the journal
+        let enchanted = journal.record(&name, "e", || enchant_file(name.clone(), "THISISMAGICO".to_string()));
+        assert_equal!(matches!(enchanted, Err(MSError::JournalError(_))), true);
+        assert_equal!(read_file(&name)?.0, data);
+
+        // When I change its magic with a journal
+        enchant_file(name.clone(), "THISISMAGICO".to_string())?;
+        let rekeyed = journal.record(&name, "rekey", || {
+            rekey_file(name.clone(), "THISISMAGICO".to_string(), &Rekey::Change("HEATPUMP".to_string()))
+        });
+
+        // Then it should fail as well
+        assert_equal!(matches!(rekeyed, Err(MSError::JournalError(_))), true);
+        assert_equal!(journal.entries()?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journaled_runs_unjournaled_commands() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::open(&dir.path().join("journal").to_string_lossy())?;
+        let name = dir.path().join("unjournaled.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..64).collect();
+
+        // Given a file exists
+        write_file(name.clone(), data.clone())?;
+
+        // When I enchant it and change its magic through a journal
+        journaled(Some(&journal), &name, "e", || enchant_file(name.clone(), "THISISMAGICO".to_string()))?;
+        journaled(Some(&journal), &name, "rekey", || {
+            rekey_file(name.clone(), "THISISMAGICO".to_string(), &Rekey::Change("HEATPUMP".to_string()))
+        })?;
+
+        // Then both should run without being recorded
+        assert_equal!(journal.entries()?.len(), 0);
+        restore_file(name.clone(), "HEATPUMP".to_string())?;
+        assert_equal!(read_file(&name)?.0, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_undo_restores_companions() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::open(&dir.path().join("journal").to_string_lossy())?;
+        let name = dir.path().join("companions.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..64).collect();
+
+        // Given a file enchanted in place without a journal
+        write_file(name.clone(), data.clone())?;
+        enchant_file_in_place(
+            name.clone(),
+            "THISISMAGICO".to_string(),
+            &EnchantOptions::default(),
+            &HeaderPlacement::Sidecar,
+        )?;
+        let (scrambled, _) = read_file(&name)?;
+        let (sidecar, _) = read_file(&sidecar_name(&name))?;
+
+        // When I restore it with a journal, which removes its sidecar
+        journal.record(&name, "r", || restore_file(name.clone(), "THISISMAGICO".to_string()))?;
+
+        // Then the sidecar should be recorded as joined to the file
+        let entries = journal.entries()?;
+        assert_equal!(entries.iter().map(|e| e.joined).collect::<Vec<_>>(), vec![false, true]);
+        assert_equal!(entries[1].change, Change::Removed { before: sidecar.clone() });
+
+        // When I undo the restoration
+        assert_equal!(journal.undo(1)?.len(), 2);
+
+        // Then both the file and its sidecar should be back
+        assert_equal!(read_file(&name)?.0, scrambled);
+        assert_equal!(read_file(&sidecar_name(&name))?.0, sidecar);
+        restore_file(name.clone(), "THISISMAGICO".to_string())?;
+        assert_equal!(read_file(&name)?.0, data);
+
+        // When I undo a journaled swap
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend(&data);
+        write_file(name.clone(), png.clone())?;
+        journal.record(&name, "swap", || swap_file(name.clone(), FileType::Zip))?;
+        journal.undo(1)?;
+
+        // Then the file should be back without its swap record
+        assert_equal!(read_file(&name)?.0, png);
+        assert_equal!(Path::new(&swap_record_name(&name)).exists(), false);
+        assert_equal!(journal.entries()?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_undo_refuses_changed_files() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::open(&dir.path().join("journal").to_string_lossy())?;
+        let name = dir.path().join("changed.bin").to_string_lossy().to_string();
+
+        // Given a file grafted with a journal and then cut without one
+        write_file(name.clone(), (0u8..64).collect())?;
+        journal.record(&name, "gp", || prefix_file(name.clone(), vec!["s:\"PK\"".to_string()]))?;
+        delete_end_file(name.clone(), 4)?;
+
+        // When I undo the graft
+        // Then it should fail leaving the file and the journal as they were
+        assert_equal!(
+            matches!(journal.undo(1), Err(MSError::JournalError(_))),
+            true
+        );
+        assert_equal!(read_file(&name)?.0.len(), 62);
+        assert_equal!(journal.entries()?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use crate::errors::MSError;
+use crate::fs::Rekey;
+use crate::ram::{EnchantOptions, MetaMagic};
+
+// the contents each `_file` operation leaves a file with, computed in
+// memory without writing anything, as needed by `--dry-run`
+
+pub fn prefix_bytes(input: &[u8], bytes: &[u8]) -> Vec<u8> {
+    let mut output = bytes.to_vec();
+    output.extend(input);
+    output
+}
+
+pub fn suffix_bytes(input: &[u8], bytes: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+    output.extend(bytes);
+    output
+}
+
+pub fn delete_start_bytes(input: &[u8], amnt: usize) -> Vec<u8> {
+    input[amnt.min(input.len())..].to_vec()
+}
+
+pub fn delete_end_bytes(input: &[u8], amnt: usize) -> Vec<u8> {
+    input[..input.len() - amnt.min(input.len())].to_vec()
+}
+
+pub fn enchant_bytes(input: Vec<u8>, magic: &str, options: &EnchantOptions) -> Result<Vec<u8>, MSError> {
+    MetaMagic::with_options(input, magic, options)?.enchant()
+}
+
+pub fn restore_bytes(input: Vec<u8>, magic: &str) -> Result<Vec<u8>, MSError> {
+    MetaMagic::from_enchanted(input, magic)?.orig()
+}
+
+pub fn rekey_bytes(input: Vec<u8>, magic: &str, rekey: &Rekey) -> Result<Vec<u8>, MSError> {
+    let mut meta = MetaMagic::from_enchanted(input, magic)?;
+    match rekey {
+        Rekey::Add(other) => meta.add_magic(other)?,
+        Rekey::Remove(other) => meta.remove_magic(other)?,
+        Rekey::Change(new) => meta.change_magic(magic, new)?,
+    }
+    meta.enchant()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_graft_and_delete_bytes() {
+        let data = [0x01, 0x02, 0x03];
+        assert_equal!(prefix_bytes(&data, &[0x00]), vec![0x00, 0x01, 0x02, 0x03]);
+        assert_equal!(suffix_bytes(&data, &[0x04]), vec![0x01, 0x02, 0x03, 0x04]);
+        assert_equal!(delete_start_bytes(&data, 1), vec![0x02, 0x03]);
+        assert_equal!(delete_end_bytes(&data, 1), vec![0x01, 0x02]);
+        assert_equal!(delete_start_bytes(&data, 5), Vec::<u8>::new());
+        assert_equal!(delete_end_bytes(&data, 5), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_enchant_restore_and_rekey_bytes() -> Result<(), MSError> {
+        let data: Vec<u8> = (0u8..64).collect();
+        let enchanted = enchant_bytes(data.clone(), "OLDMAGIC", &EnchantOptions::default())?;
+        let rekeyed = rekey_bytes(enchanted.clone(), "OLDMAGIC", &Rekey::Change("NEWMAGIC".to_string()))?;
+        assert_equal!(restore_bytes(enchanted, "OLDMAGIC")?, data);
+        assert_equal!(restore_bytes(rekeyed.clone(), "OLDMAGIC"), Err(MSError::MagicMismatch));
+        assert_equal!(restore_bytes(rekeyed, "NEWMAGIC")?, data);
+        Ok(())
+    }
+}
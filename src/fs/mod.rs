@@ -1,16 +1,35 @@
 use crate::errors::MSError;
-use crate::p::str_to_u128;
-use crate::ram::{crc32, MetaMagic};
-use hex;
-use std::collections::VecDeque;
-use std::fs::File;
+use crate::p::strs_to_bytes;
+use crate::ram::{crc32, layer_depth, unwind, xform, EnchantOptions, Transform};
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+pub mod change;
+pub mod cut;
+pub mod inplace;
+#[cfg(feature = "serde")]
+pub mod journal;
+pub mod mem;
+#[cfg(feature = "serde")]
+pub mod recipe;
+pub mod swap;
+pub use change::*;
+pub use cut::*;
+pub use inplace::*;
+#[cfg(feature = "serde")]
+pub use journal::*;
+pub use mem::*;
+#[cfg(feature = "serde")]
+pub use recipe::*;
+pub use swap::*;
 
 // use magic_switcheroo::{hexdecs, CAR_SIZE};
 pub fn read_file_into_vec(filename: &String, contents: &mut Vec<u8>) {
     let mut f = File::open(filename).unwrap();
     f.read_to_end(contents)
-        .expect(&format!("failed to read file '{}'", filename));
+        .unwrap_or_else(|_| panic!("failed to read file '{}'", filename));
 }
 pub fn read_file(filename: &String) -> Result<(Vec<u8>, Vec<u8>), MSError> {
     let mut contents = Vec::new();
@@ -22,77 +41,195 @@ pub fn write_file(filename: String, data: Vec<u8>) -> Result<(), MSError> {
     let mut file = File::create(filename)?;
     Ok(file.write_all(&data)?)
 }
+
+/// writes `data` to a temporary file next to `filename` then renames it
+/// over `filename`, so readers see either the old or the new contents;
+/// the permissions of `filename`, if it exists, are kept
+pub fn write_file_atomically(filename: &str, data: &[u8]) -> Result<(), MSError> {
+    let path = Path::new(filename);
+    let temporary = path.with_file_name(format!(".{}.ms-tmp", base_name(filename)));
+    let mut file = File::create(&temporary)?;
+    let written = match std::fs::metadata(path) {
+        Ok(metadata) => file.set_permissions(metadata.permissions()),
+        Err(_) => Ok(()),
+    };
+    let written = written.and_then(|_| file.write_all(data)).and_then(|_| file.sync_all());
+    if let Err(e) = written.and_then(|_| std::fs::rename(&temporary, path)) {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(e.into());
+    }
+    Ok(())
+}
+/// enchants the file recording the type detected from its first bytes
 pub fn enchant_file(filename: String, magic: String) -> Result<(), MSError> {
+    let options = EnchantOptions {
+        file_type: true,
+        ..EnchantOptions::default()
+    };
+    enchant_file_with(filename, magic, &options)
+}
+
+pub fn enchant_file_with(filename: String, magic: String, options: &EnchantOptions) -> Result<(), MSError> {
     let (read, _) = read_file(&filename)?;
+    write_file(filename.clone(), enchant_bytes(read, &magic, options)?)
+}
 
-    let meta = MetaMagic::new(read, &magic)?;
-    Ok(write_file(filename, meta.enchant()?)?)
+/// the name of the file without its directories, as recorded by `ms e --keep-name`
+pub fn base_name(filename: &str) -> String {
+    match Path::new(filename).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => filename.to_string(),
+    }
 }
 
+/// restores the file, in place if it has a sidecar
 pub fn restore_file(filename: String, magic: String) -> Result<(), MSError> {
+    if Path::new(&sidecar_name(&filename)).exists() {
+        return restore_file_in_place(filename, magic, &HeaderPlacement::Sidecar);
+    }
     let (raw, _) = read_file(&filename)?;
-    let meta = MetaMagic::from_enchanted(raw, &magic)?;
-    let restored = meta.orig();
-    write_file(filename, restored)?;
-    Ok(())
+    write_file(filename.clone(), restore_bytes(raw, &magic)?)
+}
+
+/// restores every layer of the file, see `unwind`, returning how many
+/// layers were restored
+pub fn restore_file_all(filename: String, magics: &[String]) -> Result<usize, MSError> {
+    let (raw, _) = read_file(&filename)?;
+    let depth = layer_depth(&raw)?;
+    write_file(filename, unwind(raw, magics)?)?;
+    Ok(depth)
+}
+
+/// one magic per line, ignoring blank lines and surrounding whitespace
+pub fn read_magic_file(filename: &str) -> Result<Vec<String>, MSError> {
+    Ok(std::fs::read_to_string(filename)?
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// a change to the key slots of an enchanted file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rekey {
+    /// lets another magic restore the file
+    Add(String),
+    /// stops a magic from restoring the file
+    Remove(String),
+    /// replaces the magic used to restore the file
+    Change(String),
+}
+
+/// changes the magics of a file enchanted with `magic` without ever
+/// writing its original contents to disk, keeping its body as is
+pub fn rekey_file(filename: String, magic: String, rekey: &Rekey) -> Result<(), MSError> {
+    let (raw, _) = read_file(&filename)?;
+    write_file_atomically(&filename, &rekey_bytes(raw, &magic, rekey)?)
 }
 
 pub fn suffix_file(filename: String, prefix: Vec<String>) -> Result<(), MSError> {
-    let (read, _) = read_file(&filename)?;
-    let mut xdata = read.to_vec();
-    for s in prefix {
-        xdata.extend(hex::decode(&format!("{:02x}", str_to_u128(&s)?))?);
-    }
-    Ok(write_file(filename, xdata)?)
+    graft_end_file(&filename, &strs_to_bytes(&prefix)?)
+}
+
+/// appends `bytes` to the file without reading it
+pub fn graft_end_file(filename: &str, bytes: &[u8]) -> Result<(), MSError> {
+    let mut file = OpenOptions::new().append(true).open(filename)?;
+    Ok(file.write_all(bytes)?)
+}
+
+/// overwrites the bytes of the file starting at `offset`
+pub fn overwrite_file(filename: &str, offset: u64, bytes: &[u8]) -> Result<(), MSError> {
+    let mut file = OpenOptions::new().write(true).open(filename)?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file.write_all(bytes)?)
+}
+
+/// applies the transforms to the file in order, or reverts them in the
+/// reverse order when `invert`
+pub fn xform_file(filename: &str, transforms: &[Transform], invert: bool) -> Result<(), MSError> {
+    let (read, _) = read_file(&filename.to_string())?;
+    write_file(filename.to_string(), xform(&read, transforms, invert)?)
 }
 
 pub fn prefix_file(filename: String, prefix: Vec<String>) -> Result<(), MSError> {
-    let (read, _) = read_file(&filename)?;
-    let mut xdata = Vec::<u8>::new();
-    for s in prefix {
-        xdata.extend(hex::decode(&format!("{:02x}", str_to_u128(&s)?))?);
+    graft_start_file(&filename, &strs_to_bytes(&prefix)?)
+}
+
+/// inserts `bytes` before the contents of the file
+pub fn graft_start_file(filename: &str, bytes: &[u8]) -> Result<(), MSError> {
+    let (read, _) = read_file(&filename.to_string())?;
+    write_file(filename.to_string(), prefix_bytes(&read, bytes))
+}
+
+pub const SHIFT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// moves everything after the first `amnt` bytes of `file` to its start,
+/// `chunk_size` bytes at a time, then truncates the leftover tail
+pub fn shift_left(file: &mut File, amnt: u64, chunk_size: usize) -> Result<(), MSError> {
+    let size = file.metadata()?.len();
+    let amnt = amnt.min(size);
+    let mut chunk = vec![0u8; chunk_size.max(1)];
+    let mut position: u64 = amnt;
+    while position < size {
+        let length = chunk.len().min((size - position) as usize);
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut chunk[..length])?;
+        file.seek(SeekFrom::Start(position - amnt))?;
+        file.write_all(&chunk[..length])?;
+        position += length as u64;
     }
-    xdata.extend(read);
-    Ok(write_file(filename, xdata)?)
+    Ok(file.set_len(size - amnt)?)
 }
 
 pub fn delete_start_file(filename: String, amnt: usize) -> Result<Vec<u8>, MSError> {
-    let (read, _) = read_file(&filename)?;
-    let mut data = VecDeque::<u8>::from(read);
-    let mut popped = Vec::<u8>::new();
-    for _ in 0..amnt {
-        match data.pop_front() {
-            Some(b) => popped.push(b),
-            None => break,
-        }
-    }
-    write_file(filename, data.into())?;
+    let mut file = OpenOptions::new().read(true).write(true).open(&filename)?;
+    let amnt = amnt.min(file.metadata()?.len() as usize);
+    let mut popped = vec![0u8; amnt];
+    file.read_exact(&mut popped)?;
+    shift_left(&mut file, amnt as u64, SHIFT_CHUNK_SIZE)?;
     Ok(popped)
 }
 
+/// truncates the last `amnt` bytes of the file, returning them in the
+/// order they had in the file
 pub fn delete_end_file(filename: String, amnt: usize) -> Result<Vec<u8>, MSError> {
-    let (read, _) = read_file(&filename)?;
-    let mut data = VecDeque::<u8>::from(read);
-    let mut popped = Vec::<u8>::new();
-    for _ in 0..amnt {
-        match data.pop_back() {
-            Some(b) => popped.push(b),
-            None => break,
-        }
-    }
-    write_file(filename, data.into())?;
+    let mut file = OpenOptions::new().read(true).write(true).open(&filename)?;
+    let size = file.metadata()?.len();
+    let amnt = amnt.min(size as usize);
+    let mut popped = vec![0u8; amnt];
+    file.seek(SeekFrom::End(-(amnt as i64)))?;
+    file.read_exact(&mut popped)?;
+    file.set_len(size - amnt as u64)?;
     Ok(popped)
 }
 
-pub fn read_start_file(filename: String, amnt: usize) -> Result<Vec<u8>, MSError> {
-    let (read, _) = read_file(&filename)?;
-    Ok(read[0..amnt].to_vec())
+/// checks that `amnt` bytes can be read from `available`, clamping
+/// `amnt` down to `available` rather than failing when `clamp` is set
+pub fn bounded(amnt: usize, available: usize, clamp: bool) -> Result<usize, MSError> {
+    if amnt <= available {
+        Ok(amnt)
+    } else if clamp {
+        Ok(available)
+    } else {
+        Err(MSError::OutOfBounds(amnt, available))
+    }
 }
 
-pub fn read_end_file(filename: String, amnt: usize) -> Result<Vec<u8>, MSError> {
-    let (read, _) = read_file(&filename)?;
-    let h = read.len() - amnt;
-    Ok(read[h..].to_vec())
+pub fn read_start_file(filename: String, amnt: usize, clamp: bool) -> Result<Vec<u8>, MSError> {
+    let mut file = File::open(&filename)?;
+    let amnt = bounded(amnt, file.metadata()?.len() as usize, clamp)?;
+    let mut read = vec![0u8; amnt];
+    file.read_exact(&mut read)?;
+    Ok(read)
+}
+
+pub fn read_end_file(filename: String, amnt: usize, clamp: bool) -> Result<Vec<u8>, MSError> {
+    let mut file = File::open(&filename)?;
+    let amnt = bounded(amnt, file.metadata()?.len() as usize, clamp)?;
+    let mut read = vec![0u8; amnt];
+    file.seek(SeekFrom::End(-(amnt as i64)))?;
+    file.read_exact(&mut read)?;
+    Ok(read)
 }
 
 #[cfg(test)]
@@ -102,10 +239,14 @@ mod tests {
     use crate::fs::delete_start_file;
     use crate::fs::enchant_file;
     use crate::fs::prefix_file;
+    use crate::fs::read_end_file;
+    use crate::fs::read_start_file;
+    use crate::fs::shift_left;
     use crate::fs::restore_file;
+    use crate::fs::{rekey_file, Rekey};
+    use crate::fs::{read_magic_file, restore_file_all};
     use crate::fs::suffix_file;
-    use crate::fs::{read_file, write_file};
-    use hex;
+    use crate::fs::{read_file, write_file, write_file_atomically};
     use k9::assert_equal;
 
     fn test_image_data() -> Vec<u8> {
@@ -122,6 +263,9 @@ mod tests {
             // </cdr>
         ])
     }
+    fn tmp_filename(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_string_lossy().to_string()
+    }
     fn create_test_image_file(filename: String) -> Result<String, MSError> {
         write_file(filename.clone(), test_image_data())?;
         Ok(filename)
@@ -138,11 +282,11 @@ mod tests {
         // When I enchant it
         enchant_file(filename, magic.clone())?;
 
-        // Then it should exist
+        // Then it should exist and record that it was a png
         let (enchanted_contents, enchanted_checksum) = read_file(&name)?;
-        assert_equal!(hex::encode(enchanted_checksum), "8b86405c");
+        assert_equal!(hex::encode(enchanted_checksum), "63716866");
         assert_equal!(
-            hex::encode(enchanted_contents), "0000000c3d00000032245448495349534d414749434fc3bec3bf487cad4daff0df6b2a00551c34cb2800000003080100000001000000524448490d0000000a1a0a0d474e5089c3bec3bf826042ae444e454900000000a66471f401000200000060639908544144490a000000c81bc4a7ffffff45544c5003000000bb"
+            hex::encode(enchanted_contents), "0000000c3d0000003225000000060100000001015448495349534d414749434fc3bec3bf487cad4daff0df6b2a00551c34cb2800000003080100000001000000524448490d0000000a1a0a0d474e5089c3bec3bf826042ae444e454900000000a66471f401000200000060639908544144490a000000c81bc4a7ffffff45544c5003000000bb"
         );
         Ok(())
     }
@@ -169,6 +313,149 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_restore_file_all() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "layers.png");
+        let keys = tmp_filename(&dir, "keys.txt");
+
+        // Given an image file enchanted three times
+        create_test_image_file(name.clone())?;
+        for magic in ["FIRST", "SECOND", "THIRD"] {
+            enchant_file(name.clone(), magic.to_string())?;
+        }
+
+        // And a file with the magics of the outermost layers first
+        write_file(keys.clone(), b"THIRD\n\n  SECOND \nFIRST\n".to_vec())?;
+        let magics = read_magic_file(&keys)?;
+        assert_equal!(magics, vec!["THIRD", "SECOND", "FIRST"]);
+
+        // When I restore the layers in the wrong order
+        // Then the failing layer should be reported and the file left as is
+        let (before, _) = read_file(&name)?;
+        assert_equal!(
+            restore_file_all(name.clone(), &["THIRD".to_string(), "FIRST".to_string()]),
+            Err(MSError::LayerFailed(2, Box::new(MSError::MagicMismatch)))
+        );
+        assert_equal!(read_file(&name)?.0, before);
+
+        // When I restore every layer in order
+        assert_equal!(restore_file_all(name.clone(), &magics)?, 3);
+
+        // Then it should have the previous contents
+        assert_equal!(read_file(&name)?.0, test_image_data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rekey_file() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "rekey.png");
+
+        // Given an image file enchanted with the magic of alice
+        create_test_image_file(name.clone())?;
+        enchant_file(name.clone(), "ALICE".to_string())?;
+
+        // When alice lets bob restore it too
+        rekey_file(name.clone(), "ALICE".to_string(), &Rekey::Add("BOB".to_string()))?;
+
+        // And then stops restoring it herself
+        rekey_file(name.clone(), "BOB".to_string(), &Rekey::Remove("ALICE".to_string()))?;
+
+        // Then alice can no longer restore it
+        assert_equal!(
+            restore_file(name.clone(), "ALICE".to_string()),
+            Err(MSError::MagicMismatch)
+        );
+
+        // And bob can not remove the last magic left
+        assert_equal!(
+            rekey_file(name.clone(), "BOB".to_string(), &Rekey::Remove("BOB".to_string())).is_err(),
+            true
+        );
+
+        // And bob gets the previous contents back
+        restore_file(name.clone(), "BOB".to_string())?;
+        let (read, _) = read_file(&name)?;
+        assert_equal!(hex::encode(read), hex::encode(test_image_data()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rekey_file_change() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "rekey-change.png");
+
+        // Given an image file enchanted with an old magic
+        create_test_image_file(name.clone())?;
+        enchant_file(name.clone(), "OLDMAGIC".to_string())?;
+
+        // When I change its magic
+        rekey_file(name.clone(), "OLDMAGIC".to_string(), &Rekey::Change("NEWMAGIC".to_string()))?;
+
+        // Then the old magic should no longer restore it
+        assert_equal!(
+            restore_file(name.clone(), "OLDMAGIC".to_string()),
+            Err(MSError::MagicMismatch)
+        );
+
+        // And a wrong magic should leave it untouched
+        let (before, _) = read_file(&name)?;
+        assert_equal!(
+            rekey_file(name.clone(), "OLDMAGIC".to_string(), &Rekey::Change("X".to_string())),
+            Err(MSError::MagicMismatch)
+        );
+        assert_equal!(read_file(&name)?.0, before);
+
+        // And no temporary file should be left behind
+        assert_equal!(std::fs::read_dir(dir.path())?.count(), 1);
+
+        // And the new magic should restore it
+        restore_file(name.clone(), "NEWMAGIC".to_string())?;
+        assert_equal!(read_file(&name)?.0, test_image_data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_file_atomically() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "atomic.bin");
+
+        // Given a file exists
+        write_file(name.clone(), vec![0x01, 0x02, 0x03])?;
+
+        // When I replace its contents atomically
+        write_file_atomically(&name, &[0x04, 0x05])?;
+
+        // Then only the new contents should remain
+        assert_equal!(read_file(&name)?.0, vec![0x04, 0x05]);
+        assert_equal!(std::fs::read_dir(dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_file_atomically_keeps_permissions() -> Result<(), MSError> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "private.bin");
+
+        // Given a file only its owner can read
+        write_file(name.clone(), vec![0x01, 0x02, 0x03])?;
+        std::fs::set_permissions(&name, std::fs::Permissions::from_mode(0o600))?;
+
+        // When I replace its contents atomically
+        write_file_atomically(&name, &[0x04, 0x05])?;
+
+        // Then it should keep its permissions
+        assert_equal!(std::fs::metadata(&name)?.permissions().mode() & 0o777, 0o600);
+        Ok(())
+    }
+
     #[test]
     fn test_suffix_file() -> Result<(), MSError> {
         let name: String = "to-suffix.png".to_string();
@@ -187,7 +474,6 @@ mod tests {
         suffix_file(
             name.clone(),
             "0x4f 0o44 0b100101"
-                .to_string()
                 .split(' ')
                 .map(|x| x.to_string())
                 .collect(),
@@ -224,7 +510,6 @@ mod tests {
         prefix_file(
             name.clone(),
             "0x4f 0o44 0b100101"
-                .to_string()
                 .split(' ')
                 .map(|x| x.to_string())
                 .collect(),
@@ -244,6 +529,34 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn test_suffix_file_typed_literals() -> Result<(), MSError> {
+        let name: String = "to-suffix-typed.png".to_string();
+
+        // Given a file exists
+        write_file(name.clone(), Vec::<u8>::from([0x89, 0x50]))?;
+
+        // When I suffix it with typed literals
+        suffix_file(
+            name.clone(),
+            ["0x100", "u16le:0x1234", "i32be:-2", "s:\"PK\"", "h:deadbeef"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+        )?;
+
+        // Then each literal should be encoded with its own width and endianness
+        let (suffixed_contents, _) = read_file(&name)?;
+        assert_equal!(
+            suffixed_contents,
+            Vec::<u8>::from([
+                0x89, 0x50, 0x01, 0x00, 0x34, 0x12, 0xff, 0xff, 0xff, 0xfe, 0x50, 0x4b, 0xde, 0xad,
+                0xbe, 0xef,
+            ])
+        );
+
+        Ok(())
+    }
+    #[test]
     fn test_delete_start_file() -> Result<(), MSError> {
         let name: String = "dsf.png".to_string();
 
@@ -301,4 +614,74 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_read_start_and_end_file() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "rsef.png");
+
+        // Given a file with 6 bytes exists
+        write_file(name.clone(), Vec::<u8>::from([0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a]))?;
+
+        // When I read within bounds
+        // Then I should get the requested bytes
+        assert_equal!(read_start_file(name.clone(), 2, false)?, vec![0x89, 0x50]);
+        assert_equal!(read_end_file(name.clone(), 2, false)?, vec![0x0d, 0x0a]);
+
+        // When I read past the end of the file
+        // Then it should fail unless clamping was requested
+        assert_equal!(
+            read_start_file(name.clone(), 7, false),
+            Err(MSError::OutOfBounds(7, 6))
+        );
+        assert_equal!(
+            read_end_file(name.clone(), 7, false),
+            Err(MSError::OutOfBounds(7, 6))
+        );
+        assert_equal!(read_start_file(name.clone(), 7, true)?.len(), 6);
+        assert_equal!(read_end_file(name.clone(), 7, true)?.len(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_start_file_in_chunks() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = tmp_filename(&dir, "dsfc.png");
+
+        // Given a file exists
+        write_file(name.clone(), (0u8..20).collect())?;
+
+        // When I shift it left by 7 bytes, 3 bytes at a time
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&name)?;
+        shift_left(&mut file, 7, 3)?;
+
+        // Then only the bytes after the first 7 should remain
+        let (contents, _) = read_file(&name)?;
+        assert_equal!(contents, (7u8..20).collect::<Vec<u8>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_more_than_file_size() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let start = tmp_filename(&dir, "dsfm.png");
+        let end = tmp_filename(&dir, "defm.png");
+
+        // Given two files with 3 bytes exist
+        write_file(start.clone(), Vec::<u8>::from([0x01, 0x02, 0x03]))?;
+        write_file(end.clone(), Vec::<u8>::from([0x01, 0x02, 0x03]))?;
+
+        // When I delete more bytes than they have
+        // Then every byte should be popped
+        assert_equal!(delete_start_file(start.clone(), 5)?, vec![0x01, 0x02, 0x03]);
+        assert_equal!(delete_end_file(end.clone(), 5)?, vec![0x01, 0x02, 0x03]);
+
+        // And the files should be empty
+        assert_equal!(read_file(&start)?.0.len(), 0);
+        assert_equal!(read_file(&end)?.0.len(), 0);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,216 @@
+use crate::errors::MSError;
+use crate::fs::{
+    delete_end_bytes, delete_start_bytes, prefix_bytes, read_file, suffix_bytes, write_file_atomically,
+};
+use crate::p::strs_to_bytes;
+use crate::ram::{rsubnseq, xform, Transform};
+use serde::{Deserialize, Serialize};
+
+fn one() -> usize {
+    1
+}
+
+/// an operation of a recipe, taking the same arguments as the command
+/// of the same name, e.g.: `{"op": "gp", "bytes": ["s:\"PK\""]}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Step {
+    Gp {
+        bytes: Vec<String>,
+    },
+    Gs {
+        bytes: Vec<String>,
+    },
+    Ds {
+        amount: usize,
+    },
+    De {
+        amount: usize,
+    },
+    /// replaces the first `count` occurrences of `from` with `to`, which
+    /// must be as long
+    Sub {
+        from: Vec<String>,
+        to: Vec<String>,
+        #[serde(default = "one")]
+        count: usize,
+    },
+    /// transforms as given to `ms xform --op`
+    Xform {
+        ops: Vec<String>,
+        #[serde(default)]
+        invert: bool,
+    },
+}
+
+impl Step {
+    pub fn apply(&self, input: &[u8]) -> Result<Vec<u8>, MSError> {
+        match self {
+            Step::Gp { bytes } => Ok(prefix_bytes(input, &strs_to_bytes(bytes)?)),
+            Step::Gs { bytes } => Ok(suffix_bytes(input, &strs_to_bytes(bytes)?)),
+            Step::Ds { amount } => Ok(delete_start_bytes(input, *amount)),
+            Step::De { amount } => Ok(delete_end_bytes(input, *amount)),
+            Step::Sub { from, to, count } => {
+                let (from, to) = (strs_to_bytes(from)?, strs_to_bytes(to)?);
+                if from.len() != to.len() {
+                    return Err(MSError::RecipeError(format!(
+                        "sub replaces {} bytes with {} bytes",
+                        from.len(),
+                        to.len()
+                    )));
+                }
+                rsubnseq(input, &from, &to, *count).map_err(MSError::VecsError)
+            }
+            Step::Xform { ops, invert } => {
+                let transforms = ops.iter().map(|op| op.parse()).collect::<Result<Vec<Transform>, MSError>>()?;
+                xform(input, &transforms, *invert)
+            }
+        }
+    }
+    /// the step undoing this one, `None` for `ds` and `de` whose deleted
+    /// bytes are not known
+    pub fn inverse(&self) -> Result<Option<Step>, MSError> {
+        Ok(match self {
+            Step::Gp { bytes } => Some(Step::Ds { amount: strs_to_bytes(bytes)?.len() }),
+            Step::Gs { bytes } => Some(Step::De { amount: strs_to_bytes(bytes)?.len() }),
+            Step::Ds { .. } | Step::De { .. } => None,
+            Step::Sub { from, to, count } => Some(Step::Sub {
+                from: to.clone(),
+                to: from.clone(),
+                count: *count,
+            }),
+            Step::Xform { ops, invert } => Some(Step::Xform {
+                ops: ops.clone(),
+                invert: !invert,
+            }),
+        })
+    }
+}
+
+/// operations applied in memory in order, the file being written once
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub steps: Vec<Step>,
+}
+
+impl Recipe {
+    pub fn parse(json: &str) -> Result<Recipe, MSError> {
+        serde_json::from_str(json).map_err(|e| MSError::RecipeError(format!("invalid recipe: {e}")))
+    }
+    pub fn apply(&self, input: &[u8]) -> Result<Vec<u8>, MSError> {
+        let mut output = input.to_vec();
+        for (index, step) in self.steps.iter().enumerate() {
+            output = match step.apply(&output) {
+                Ok(output) => output,
+                Err(e) => return Err(MSError::RecipeError(format!("step {}: {e}", index + 1))),
+            };
+        }
+        Ok(output)
+    }
+    /// the inverse of each step in the reverse order, failing with
+    /// `MSError::RecipeError` if any step is not reversible
+    pub fn inverse(&self) -> Result<Recipe, MSError> {
+        let mut steps = Vec::new();
+        for (index, step) in self.steps.iter().enumerate().rev() {
+            match step.inverse()? {
+                Some(inverse) => steps.push(inverse),
+                None => return Err(MSError::RecipeError(format!("step {} is not reversible", index + 1))),
+            }
+        }
+        Ok(Recipe { steps })
+    }
+    /// the contents the inverse recipe gives, failing unless applying the
+    /// recipe to them gives `input` back, as a `sub` replacing the first
+    /// occurrences only is not always undone by swapping its bytes
+    pub fn revert(&self, input: &[u8]) -> Result<Vec<u8>, MSError> {
+        let output = self.inverse()?.apply(input)?;
+        if self.apply(&output)? != input {
+            return Err(MSError::RecipeError("the recipe cannot be inverted for these contents".to_string()));
+        }
+        Ok(output)
+    }
+}
+
+pub fn read_recipe(path: &str) -> Result<Recipe, MSError> {
+    Recipe::parse(&std::fs::read_to_string(path)?)
+}
+
+/// applies the recipe, or its inverse when `invert`, to the file writing
+/// it once, atomically, after every step succeeded
+pub fn apply_recipe_file(filename: &str, recipe: &Recipe, invert: bool) -> Result<(), MSError> {
+    let (read, _) = read_file(&filename.to_string())?;
+    let output = if invert { recipe.revert(&read)? } else { recipe.apply(&read)? };
+    write_file_atomically(filename, &output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::write_file;
+    use k9::assert_equal;
+
+    fn test_recipe() -> Result<Recipe, MSError> {
+        Recipe::parse(
+            r#"{"steps": [
+                {"op": "gp", "bytes": ["s:\"PK\""]},
+                {"op": "sub", "from": ["h:0203"], "to": ["h:ffff"]},
+                {"op": "xform", "ops": ["reverse:0..4"]},
+                {"op": "gs", "bytes": ["h:00", "h:01"]}
+            ]}"#,
+        )
+    }
+
+    #[test]
+    fn test_recipe_apply_and_revert() -> Result<(), MSError> {
+        let data: Vec<u8> = (0u8..8).collect();
+        let recipe = test_recipe()?;
+        let applied = recipe.apply(&data)?;
+        assert_equal!(applied, vec![0x01, 0x00, 0x4b, 0x50, 0xff, 0xff, 4, 5, 6, 7, 0x00, 0x01]);
+        assert_equal!(recipe.revert(&applied)?, data);
+        assert_equal!(recipe.inverse()?.steps.first(), Some(&Step::De { amount: 2 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recipe_errors() -> Result<(), MSError> {
+        let data: Vec<u8> = (0u8..8).collect();
+        let irreversible = Recipe { steps: vec![Step::Ds { amount: 1 }] };
+        assert_equal!(irreversible.inverse().is_err(), true);
+        assert_equal!(Recipe::parse(r#"{"steps": [{"op": "nope"}]}"#).is_err(), true);
+        let uneven = Recipe {
+            steps: vec![Step::Sub { from: vec!["h:00".to_string()], to: vec!["h:0000".to_string()], count: 1 }],
+        };
+        assert_equal!(uneven.apply(&data).is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_recipe_file() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("recipe.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0u8..8).collect();
+        write_file(name.clone(), data.clone())?;
+
+        // When a recipe fails halfway
+        let failing = Recipe {
+            steps: vec![
+                Step::Gp { bytes: vec!["h:00".to_string()] },
+                Step::Ds { amount: 1 },
+                Step::Gp { bytes: vec!["x:invalid".to_string()] },
+            ],
+        };
+        assert_equal!(apply_recipe_file(&name, &failing, false).is_err(), true);
+
+        // Then the file should be left as it was
+        assert_equal!(read_file(&name)?.0, data);
+
+        // When a recipe is applied and inverted
+        apply_recipe_file(&name, &test_recipe()?, false)?;
+        assert_equal!(read_file(&name)?.0.len(), 12);
+        apply_recipe_file(&name, &test_recipe()?, true)?;
+
+        // Then the file should have the previous contents
+        assert_equal!(read_file(&name)?.0, data);
+        Ok(())
+    }
+}
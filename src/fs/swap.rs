@@ -0,0 +1,216 @@
+use crate::errors::MSError;
+use crate::fs::{read_file, write_file_atomically, write_sidecar};
+use crate::pad::unpad32;
+use crate::ram::usize_to_hex;
+use crate::sig::{detect_signature, FileType};
+use std::path::Path;
+
+/// starts the record of the bytes replaced by a swap
+pub const SWAP_MARK: &[u8] = b"MSWP";
+/// bytes read from the start of a file to detect its signature
+pub const DETECT_SIZE: usize = 512;
+/// appended to the name of a swapped file to name its swap record
+pub const SWAP_SUFFIX: &str = ".swap";
+
+pub fn swap_record_name(filename: &str) -> String {
+    format!("{filename}{SWAP_SUFFIX}")
+}
+
+/// the record of the bytes replaced by a swap: `SWAP_MARK` then the
+/// offset, length and original bytes of each replaced part
+pub fn swap_record(replaced: &[(usize, Vec<u8>)]) -> Result<Vec<u8>, MSError> {
+    let mut record = SWAP_MARK.to_vec();
+    for (offset, bytes) in replaced {
+        record.extend(usize_to_hex(*offset)?);
+        record.extend(usize_to_hex(bytes.len())?);
+        record.extend(bytes);
+    }
+    Ok(record)
+}
+
+/// the parts recorded by `swap_record`, `None` if `record` is not one
+pub fn parse_swap_record(record: &[u8]) -> Option<Vec<(usize, Vec<u8>)>> {
+    let mut rest = record.strip_prefix(SWAP_MARK)?;
+    let mut replaced = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return None;
+        }
+        let offset = unpad32(rest[..4].to_vec())[0] as usize;
+        let len = unpad32(rest[4..8].to_vec())[0] as usize;
+        let bytes = rest[8..].get(..len)?;
+        replaced.push((offset, bytes.to_vec()));
+        rest = &rest[8 + len..];
+    }
+    Some(replaced)
+}
+
+/// the contents of a file swapped to `to` and its swap record: the bytes
+/// of each part of the signature of `to` are written over the file, which
+/// keeps its size, and the bytes they replaced are recorded; also returns
+/// the type the file had
+pub fn swap_bytes(filename: &str, input: &[u8], to: FileType) -> Result<(Vec<u8>, Vec<u8>, FileType), MSError> {
+    let from = match detect_signature(&input[..input.len().min(DETECT_SIZE)]) {
+        Some(signature) => signature.file_type,
+        None => return Err(MSError::UnknownFileType(filename.to_string())),
+    };
+    let signature = to.signature();
+    let span = signature.span();
+    if span > input.len() {
+        return Err(MSError::OutOfBounds(span, input.len()));
+    }
+    let mut output = input.to_vec();
+    let mut replaced = Vec::new();
+    for (offset, bytes) in signature.parts {
+        replaced.push((*offset, input[*offset..*offset + bytes.len()].to_vec()));
+        output[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+    }
+    Ok((output, swap_record(&replaced)?, from))
+}
+
+/// the contents a file swapped by `swap_bytes` had before, given its
+/// swap record
+pub fn unswap_bytes(filename: &str, input: &[u8], record: &[u8]) -> Result<Vec<u8>, MSError> {
+    let replaced = match parse_swap_record(record) {
+        Some(replaced) => replaced,
+        None => return Err(MSError::NotSwapped(filename.to_string())),
+    };
+    let mut output = input.to_vec();
+    for (offset, bytes) in replaced {
+        if offset + bytes.len() > output.len() {
+            return Err(MSError::OutOfBounds(offset + bytes.len(), output.len()));
+        }
+        output[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    Ok(output)
+}
+
+/// replaces the signature of the file with the signature of `to`, keeping
+/// the replaced bytes in its swap record, see `swap_record_name`; fails
+/// with `MSError::SidecarExists` if the file is already swapped; returns
+/// the type the file had
+pub fn swap_file(filename: String, to: FileType) -> Result<FileType, MSError> {
+    let (read, _) = read_file(&filename)?;
+    let (output, record, from) = swap_bytes(&filename, &read, to)?;
+    write_sidecar(&swap_record_name(&filename), &record)?;
+    write_file_atomically(&filename, &output)?;
+    Ok(from)
+}
+
+/// puts back the bytes replaced by `swap_file`, removing the swap record
+pub fn unswap_file(filename: String) -> Result<(), MSError> {
+    if !Path::new(&swap_record_name(&filename)).exists() {
+        return Err(MSError::NotSwapped(filename));
+    }
+    let (record, _) = read_file(&swap_record_name(&filename))?;
+    let (read, _) = read_file(&filename)?;
+    write_file_atomically(&filename, &unswap_bytes(&filename, &read, &record)?)?;
+    std::fs::remove_file(swap_record_name(&filename))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{read_file, write_file};
+    use crate::sig::detect;
+    use k9::assert_equal;
+
+    fn test_png() -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::from([
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52,
+        ]);
+        data.extend((0u8..50).collect::<Vec<u8>>());
+        data
+    }
+
+    #[test]
+    fn test_swap_and_unswap_file() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("swap.png").to_string_lossy().to_string();
+
+        // Given a png file exists
+        write_file(name.clone(), test_png())?;
+
+        // When I swap it to a zip
+        assert_equal!(swap_file(name.clone(), FileType::Zip)?, FileType::Png);
+
+        // Then it should look like a zip of the same size
+        let (swapped, _) = read_file(&name)?;
+        assert_equal!(detect(&swapped), Some(FileType::Zip));
+        assert_equal!(swapped.len(), test_png().len());
+        assert_equal!(&swapped[4..], &test_png()[4..]);
+
+        // And the replaced bytes should be in its swap record
+        let (record, _) = read_file(&swap_record_name(&name))?;
+        assert_equal!(hex::encode(&record), "4d535750000000000000000489504e47");
+
+        // And swapping it again should fail
+        assert_equal!(
+            swap_file(name.clone(), FileType::Pdf),
+            Err(MSError::SidecarExists(swap_record_name(&name)))
+        );
+
+        // When I unswap it
+        unswap_file(name.clone())?;
+
+        // Then it should have its original contents
+        let (restored, _) = read_file(&name)?;
+        assert_equal!(restored, test_png());
+        assert_equal!(Path::new(&swap_record_name(&name)).exists(), false);
+
+        // And unswapping it again should fail
+        assert_equal!(unswap_file(name.clone()), Err(MSError::NotSwapped(name.clone())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_and_unswap_bytes() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("swap-bytes.png").to_string_lossy().to_string();
+        write_file(name.clone(), test_png())?;
+
+        let (swapped, record, from) = swap_bytes(&name, &test_png(), FileType::Zip)?;
+        swap_file(name.clone(), FileType::Zip)?;
+        assert_equal!(from, FileType::Png);
+        assert_equal!(read_file(&name)?.0, swapped);
+        assert_equal!(read_file(&swap_record_name(&name))?.0, record);
+
+        assert_equal!(unswap_bytes(&name, &swapped, &record)?, test_png());
+        assert_equal!(unswap_bytes(&name, &swapped, b"MSWP\x00"), Err(MSError::NotSwapped(name.clone())));
+        assert_equal!(
+            swap_bytes(&name, b"plain text", FileType::Zip),
+            Err(MSError::UnknownFileType(name.clone()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_records_only_the_replaced_parts() -> Result<(), MSError> {
+        // Given a png large enough to hold a tar signature
+        let mut data = test_png();
+        data.extend([0x2a; 300]);
+
+        // When I swap it to a tar
+        let (swapped, record, _) = swap_bytes("swap.png", &data, FileType::Tar)?;
+
+        // Then only the bytes of the tar signature should be replaced and recorded
+        assert_equal!(swapped.len(), data.len());
+        assert_equal!(parse_swap_record(&record), Some(vec![(257, vec![0x2a; 5])]));
+        assert_equal!(unswap_bytes("swap.png", &swapped, &record)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_unknown_file() -> Result<(), MSError> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("swap.txt").to_string_lossy().to_string();
+        write_file(name.clone(), b"plain text".to_vec())?;
+        assert_equal!(
+            swap_file(name.clone(), FileType::Zip),
+            Err(MSError::UnknownFileType(name.clone()))
+        );
+        Ok(())
+    }
+}
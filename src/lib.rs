@@ -2,5 +2,8 @@ pub mod fs;
 pub mod p;
 pub mod errors;
 pub mod pad;
+#[cfg(feature = "cli")]
 pub mod cli;
+pub mod out;
 pub mod ram;
+pub mod sig;
@@ -1,83 +1,385 @@
 // use std::path::{Path};
-use ansi_term;
 //use clap::{Parser, Subcommand};
 pub use magic_switcheroo::errors::MSError;
-use magic_switcheroo::cli::{Engine, Commands};
+use magic_switcheroo::cli::{Engine, Commands, EOps, ROps, RekeyOps};
 use magic_switcheroo::fs::{
-    delete_end_file,
-    delete_start_file,
+    delete_end_bytes,
+    delete_start_bytes,
     read_end_file,
     read_start_file,
-    enchant_file,
+    enchant_file_with,
+    journaled,
+    unjournaled,
+    Change,
+    Journal,
+    graft_file_from,
+    graft_bytes,
+    cut_file,
+    Side,
+    enchant_file_in_place,
+    restore_file_in_place,
+    enchant_bytes,
+    enchant_bytes_in_place,
+    restore_bytes,
+    restore_bytes_in_place,
+    sidecar_name,
+    HeaderPlacement,
+    base_name,
+    swap_file,
+    unswap_file,
+    swap_bytes,
+    unswap_bytes,
+    swap_record_name,
     restore_file,
+    restore_file_all,
+    read_magic_file,
+    rekey_file,
+    rekey_bytes,
+    Rekey,
     prefix_file,
+    prefix_bytes,
     suffix_file,
+    suffix_bytes,
+    xform_file,
+    apply_recipe_file,
+    read_recipe,
 };
-use magic_switcheroo::ram::{Digest};
+use magic_switcheroo::fs::read_file;
+use magic_switcheroo::out::{diff, dump, paint_field, preview, render};
+use magic_switcheroo::p::strs_to_bytes;
+use magic_switcheroo::ram::{layer_depth, unwind, xform, EnchantOptions, Layout, Region, EXT_FILE_NAME};
+use magic_switcheroo::sig::{detect, detect_enchanted, FileType};
 use std::error::Error;
-use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 // use magic_switcheroo::{hexdecs, CAR_SIZE};
 
 
-#[derive(Debug, Clone)]
-pub struct DigestMismatch {
-    expected: Digest,
-    actual: Digest,
+pub fn describe_type(file_type: Option<FileType>) -> String {
+    match file_type {
+        Some(t) => format!("{} ({})", t.description(), t.name()),
+        None => "unknown".to_string(),
+    }
 }
-impl DigestMismatch {
-    pub fn new(expected: Digest, actual: Digest) -> DigestMismatch {
-        DigestMismatch {
-            expected: expected.clone(),
-            actual: actual.clone(),
-        }
+
+pub fn describe_change(change: &Change) -> String {
+    match change {
+        Change::GraftStart { added } => format!("+{} bytes at the start", added.len()),
+        Change::GraftEnd { added } => format!("+{} bytes at the end", added.len()),
+        Change::DeleteStart { removed } => format!("-{} bytes at the start", removed.len()),
+        Change::DeleteEnd { removed } => format!("-{} bytes at the end", removed.len()),
+        Change::Overwrite { offset, before } => format!("overwrote {} bytes at {offset}", before.len()),
+        Change::Replace { before } => format!("rewrote {} bytes", before.len()),
+        Change::Created => "created".to_string(),
+        Change::Removed { before } => format!("removed {} bytes", before.len()),
     }
 }
 
-impl fmt::Display for DigestMismatch {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "digest mismatch {:#?} != {:#?}",
-            self.expected, self.actual
-        )
+pub fn type_name(file_type: Option<FileType>) -> String {
+    match file_type {
+        Some(t) => t.name().to_string(),
+        None => "unknown".to_string(),
     }
 }
 
-pub fn ac(code: u8) -> ansi_term::Style {
-    return ansi_term::Colour::Fixed(code.try_into().unwrap()).bold();
+/// the journal all changes are recorded to, when asked for one
+fn require_journal(journal: Option<&Journal>) -> Result<&Journal, MSError> {
+    match journal {
+        Some(journal) => Ok(journal),
+        None => Err(MSError::JournalError("no journal, pass --journal or set MS_JOURNAL".to_string())),
+    }
+}
+
+/// tells that `command` changes the file without a journal entry, see
+/// `unjournaled`
+fn warn_unjournaled(journal: Option<&Journal>, command: &str) {
+    if let (Some(_), Some(kept)) = (journal, unjournaled(command)) {
+        eprintln!("warning: {command} is not journaled as its entry would keep {kept}");
+    }
+}
+
+pub fn enchant_options(ops: &EOps) -> EnchantOptions {
+    EnchantOptions {
+        file_type: true,
+        file_name: if ops.keep_name { Some(base_name(&ops.filename)) } else { None },
+        digest: ops.digest,
+        mac: ops.mac,
+        recipients: ops.magic[1..].to_vec(),
+        region: ops.range.or(ops.tail.map(Region::Tail)),
+        spell: ops.spell,
+    }
+}
+
+pub fn enchant_placement(ops: &EOps) -> Option<HeaderPlacement> {
+    match (ops.inplace, ops.reserved) {
+        (true, Some(reserved)) => Some(HeaderPlacement::Reserved(reserved)),
+        (true, None) => Some(HeaderPlacement::Sidecar),
+        (false, _) => None,
+    }
+}
+
+pub fn restore_magics(ops: &ROps) -> Result<Vec<String>, MSError> {
+    match (&ops.magic, &ops.magic_file) {
+        (Some(magic), _) => Ok(vec![magic.to_string()]),
+        (_, Some(magic_file)) => read_magic_file(magic_file),
+        _ => unreachable!("clap requires --magic or --magic-file"),
+    }
+}
+
+pub fn rekey_of(ops: &RekeyOps) -> Rekey {
+    match (&ops.to, &ops.add, &ops.remove) {
+        (Some(to), _, _) => Rekey::Change(to.to_string()),
+        (_, Some(add), _) => Rekey::Add(add.to_string()),
+        (_, _, Some(remove)) => Rekey::Remove(remove.to_string()),
+        _ => unreachable!("clap requires --to, --add or --remove"),
+    }
+}
+
+pub fn swap_target(name: &str) -> Result<FileType, MSError> {
+    match FileType::from_name(name) {
+        Some(to) => Ok(to),
+        None => Err(MSError::UnknownFileType(name.to_string())),
+    }
+}
+
+/// a file with its contents before and after a command
+type Simulated = (String, Vec<u8>, Vec<u8>);
+
+/// the file a mutating command would change with its contents before and
+/// after, computed in memory for `--dry-run`
+fn simulate(command: &Commands) -> Result<Option<Simulated>, MSError> {
+    let filename = match command {
+        Commands::Swap(ops) => &ops.filename,
+        Commands::Unswap(ops) => &ops.filename,
+        Commands::E(ops) => &ops.filename,
+        Commands::R(ops) => &ops.filename,
+        Commands::Rekey(ops) => &ops.filename,
+        Commands::Gp(ops) => &ops.filename,
+        Commands::Gs(ops) => &ops.filename,
+        Commands::Graft(ops) => &ops.filename,
+        Commands::Ds(ops) => &ops.filename,
+        Commands::De(ops) => &ops.filename,
+        Commands::Xform(ops) => &ops.filename,
+        Commands::Apply(ops) => &ops.filename,
+        _ => return Ok(None),
+    };
+    let (before, _) = read_file(filename)?;
+    let after = match command {
+        Commands::Swap(ops) => swap_bytes(filename, &before, swap_target(&ops.to)?)?.0,
+        Commands::Unswap(_) if Path::new(&swap_record_name(filename)).exists() => {
+            unswap_bytes(filename, &before, &read_file(&swap_record_name(filename))?.0)?
+        }
+        Commands::Unswap(_) => return Err(MSError::NotSwapped(filename.to_string())),
+        Commands::E(ops) => {
+            let options = enchant_options(ops);
+            match enchant_placement(ops) {
+                Some(placement) => enchant_bytes_in_place(&before, &ops.magic[0], &options, &placement)?.0,
+                None => enchant_bytes(before.clone(), &ops.magic[0], &options)?,
+            }
+        }
+        Commands::R(ops) => {
+            let magics = restore_magics(ops)?;
+            let sidecar = sidecar_name(filename);
+            if ops.all {
+                unwind(before.clone(), &magics)?
+            } else if let Some(reserved) = ops.reserved {
+                restore_bytes_in_place(&before, None, &magics[0], &HeaderPlacement::Reserved(reserved))?
+            } else if Path::new(&sidecar).exists() {
+                let (header, _) = read_file(&sidecar)?;
+                restore_bytes_in_place(&before, Some(&header), &magics[0], &HeaderPlacement::Sidecar)?
+            } else {
+                restore_bytes(before.clone(), &magics[0])?
+            }
+        }
+        Commands::Rekey(ops) => rekey_bytes(before.clone(), &ops.magic, &rekey_of(ops))?,
+        Commands::Gp(ops) => prefix_bytes(&before, &strs_to_bytes(&ops.bytes)?),
+        Commands::Gs(ops) => suffix_bytes(&before, &strs_to_bytes(&ops.bytes)?),
+        Commands::Graft(ops) => graft_bytes(&before, &read_file(&ops.from)?.0)?,
+        Commands::Ds(ops) => delete_start_bytes(&before, ops.amount),
+        Commands::De(ops) => delete_end_bytes(&before, ops.amount),
+        Commands::Xform(ops) => xform(&before, &ops.ops, ops.invert)?,
+        Commands::Apply(ops) => {
+            let recipe = read_recipe(&ops.recipe)?;
+            if ops.invert { recipe.revert(&before)? } else { recipe.apply(&before)? }
+        }
+        _ => unreachable!("only mutating commands have a filename"),
+    };
+    Ok(Some((filename.to_string(), before, after)))
 }
 
 pub fn main() -> Result<(), Box<dyn Error>> {
-    match &Engine::start() {
+    let engine = Engine::load();
+    let journal = match &engine.journal {
+        Some(dir) => Some(Journal::open(dir)?),
+        None => None,
+    };
+    let journal = journal.as_ref();
+    if engine.dry_run {
+        if let Some((filename, before, after)) = simulate(&engine.commands)? {
+            println!("{}\n{}", filename, preview(&before, &after));
+            return Ok(());
+        }
+    }
+    match &engine.commands {
+        Commands::Swap(ops) => {
+            let to = swap_target(&ops.to)?;
+            let from = journaled(journal, &ops.filename, "swap", || swap_file(ops.filename.to_string(), to))?;
+            eprintln!("swap {} {} -> {}", ops.filename, from, to);
+        }
+        Commands::Unswap(ops) => {
+            journaled(journal, &ops.filename, "unswap", || unswap_file(ops.filename.to_string()))?;
+            eprintln!("unswap {}", ops.filename);
+        }
         Commands::E(ops) => {
-            enchant_file(ops.filename.to_string(), ops.magic.to_string())?;
+            warn_unjournaled(journal, "e");
+            let options = enchant_options(ops);
+            let magic = ops.magic[0].to_string();
+            journaled(journal, &ops.filename, "e", || match enchant_placement(ops) {
+                Some(placement) => enchant_file_in_place(ops.filename.to_string(), magic, &options, &placement),
+                None => enchant_file_with(ops.filename.to_string(), magic, &options),
+            })?;
         }
         Commands::R(ops) => {
-            restore_file(ops.filename.to_string(), ops.magic.to_string())?;
+            let magics = restore_magics(ops)?;
+            if ops.all {
+                let depth = journaled(journal, &ops.filename, "r", || {
+                    restore_file_all(ops.filename.to_string(), &magics)
+                })?;
+                eprintln!("r {} ({} layers)", ops.filename, depth);
+            } else if let Some(reserved) = ops.reserved {
+                let placement = HeaderPlacement::Reserved(reserved);
+                journaled(journal, &ops.filename, "r", || {
+                    restore_file_in_place(ops.filename.to_string(), magics[0].to_string(), &placement)
+                })?;
+            } else {
+                journaled(journal, &ops.filename, "r", || {
+                    restore_file(ops.filename.to_string(), magics[0].to_string())
+                })?;
+            }
+        }
+        Commands::Layers(ops) => {
+            for filename in &ops.filenames {
+                let (contents, _) = read_file(filename)?;
+                println!("{}: {} layers", filename, layer_depth(&contents)?);
+            }
+        }
+        Commands::Rekey(ops) => {
+            warn_unjournaled(journal, "rekey");
+            let rekey = rekey_of(ops);
+            journaled(journal, &ops.filename, "rekey", || {
+                rekey_file(ops.filename.to_string(), ops.magic.to_string(), &rekey)
+            })?;
+            eprintln!("rekey {}", ops.filename);
         }
         Commands::Gp(ops) => {
-            prefix_file(ops.filename.to_string(), ops.bytes.to_vec())?;
+            journaled(journal, &ops.filename, "gp", || prefix_file(ops.filename.to_string(), ops.bytes.to_vec()))?;
             eprintln!("gp {}", ops.filename);
         }
         Commands::Gs(ops) => {
-            suffix_file(ops.filename.to_string(), ops.bytes.to_vec())?;
+            journaled(journal, &ops.filename, "gs", || suffix_file(ops.filename.to_string(), ops.bytes.to_vec()))?;
             eprintln!("gs {}", ops.filename);
         }
+        Commands::Graft(ops) => {
+            let side = journaled(journal, &ops.filename, "graft", || graft_file_from(&ops.filename, &ops.from))?;
+            eprintln!("graft {} {:?} <- {}", ops.filename, side, ops.from);
+        }
         Commands::Ds(ops) => {
-            let start = delete_start_file(ops.filename.to_string(), ops.amount)?;
+            let start = journaled(journal, &ops.filename, "ds", || {
+                cut_file(&ops.filename, Side::Start, ops.amount, ops.save_to.as_deref())
+            })?;
             println!("{}", start.iter().map(|x| format!("0x{:02x}", x)).collect::<Vec<String>>().join(" "));
         }
         Commands::De(ops) => {
-            let end = delete_end_file(ops.filename.to_string(), ops.amount)?;
+            let end = journaled(journal, &ops.filename, "de", || {
+                cut_file(&ops.filename, Side::End, ops.amount, ops.save_to.as_deref())
+            })?;
             println!("{}", end.iter().map(|x| format!("0x{:02x}", x)).collect::<Vec<String>>().join(" "));
         }
+        Commands::Xform(ops) => {
+            journaled(journal, &ops.filename, "xform", || xform_file(&ops.filename, &ops.ops, ops.invert))?;
+            eprintln!("xform {}", ops.filename);
+        }
+        Commands::Apply(ops) => {
+            let recipe = read_recipe(&ops.recipe)?;
+            journaled(journal, &ops.filename, "apply", || apply_recipe_file(&ops.filename, &recipe, ops.invert))?;
+            eprintln!("apply {} {} ({} steps)", ops.recipe, ops.filename, recipe.steps.len());
+        }
+        Commands::Diff(ops) => {
+            let (left, _) = read_file(&ops.left)?;
+            let (right, _) = read_file(&ops.right)?;
+            println!("{}", diff(&ops.left, &left, &ops.right, &right, ops.context));
+        }
+        Commands::Undo(ops) if engine.dry_run => {
+            for entry in require_journal(journal)?.pending(ops.steps)? {
+                println!("would undo {} {} ({})", entry.command, entry.path, describe_change(&entry.change));
+            }
+        }
+        Commands::Undo(ops) => {
+            for entry in require_journal(journal)?.undo(ops.steps)? {
+                eprintln!("undo {} {}", entry.command, entry.path);
+            }
+        }
+        Commands::History(ops) => {
+            for entry in require_journal(journal)?.history(&ops.filename)? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    entry.command,
+                    hex::encode(&entry.before_crc),
+                    hex::encode(&entry.after_crc),
+                    describe_change(&entry.change)
+                );
+            }
+        }
         Commands::Rs(ops) => {
-            let start = read_start_file(ops.filename.to_string(), ops.amount)?;
-            println!("{}", start.iter().map(|x| format!("0x{:02x}", x)).collect::<Vec<String>>().join(" "));
+            let start = read_start_file(ops.filename.to_string(), ops.amount, ops.clamp)?;
+            io::stdout().write_all(&render(&start, ops.format))?;
         }
         Commands::Re(ops) => {
-            let end = read_end_file(ops.filename.to_string(), ops.amount)?;
-            println!("{}", end.iter().map(|x| format!("0x{:02x}", x)).collect::<Vec<String>>().join(" "));
+            let end = read_end_file(ops.filename.to_string(), ops.amount, ops.clamp)?;
+            io::stdout().write_all(&render(&end, ops.format))?;
+        }
+        Commands::Dump(ops) => {
+            let (contents, _) = read_file(&ops.filename)?;
+            println!("{}", dump(&contents, ops.offset, ops.len, |index, text| if ops.color {
+                paint_field(index, text)
+            } else {
+                text.to_string()
+            }));
+        }
+        Commands::Layout(ops) => {
+            let (contents, _) = read_file(&ops.filename)?;
+            println!("{}", Layout::parse(&contents)?.table(&contents));
+        }
+        Commands::What(ops) => {
+            for filename in &ops.filenames {
+                let (contents, _) = read_file(filename)?;
+                match detect_enchanted(&contents) {
+                    Some(original) => println!("{}: enchanted {}", filename, describe_type(original)),
+                    None => println!("{}: {}", filename, describe_type(detect(&contents))),
+                }
+            }
+        }
+        Commands::Ls(ops) => {
+            for filename in &ops.filenames {
+                let (contents, _) = read_file(filename)?;
+                let layout = match Layout::parse(&contents) {
+                    Ok(layout) => layout,
+                    Err(_) => {
+                        println!("{}\tplain\t{}\t-", filename, type_name(detect(&contents)));
+                        continue;
+                    }
+                };
+                let original_name = match layout.extensions(&contents)?.get(EXT_FILE_NAME) {
+                    Some(name) => String::from_utf8_lossy(&name).to_string(),
+                    None => "-".to_string(),
+                };
+                println!(
+                    "{}\tenchanted\t{}\t{}",
+                    filename,
+                    type_name(detect_enchanted(&contents).flatten()),
+                    original_name
+                );
+            }
         }
     }
     Ok(())
@@ -0,0 +1,187 @@
+use crate::fs::Change;
+
+/// how many equal bytes are shown around each differing range
+pub const DIFF_CONTEXT: usize = 8;
+/// how many bytes of a differing range are shown before eliding the rest
+pub const DIFF_SHOWN: usize = 32;
+
+/// a range of `left` whose bytes differ from the bytes at the same offset
+/// of `right`, either side being shorter when the files differ in size
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub offset: usize,
+    pub left_len: usize,
+    pub right_len: usize,
+}
+
+/// the ranges where `left` and `right` differ, comparing the bytes at the
+/// same offsets; ranges apart by less than `context` equal bytes are merged
+pub fn hunks(left: &[u8], right: &[u8], context: usize) -> Vec<Hunk> {
+    let common = left.len().min(right.len());
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut offset = 0;
+    while offset < common {
+        if left[offset] == right[offset] {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        while offset < common && left[offset] != right[offset] {
+            offset += 1;
+        }
+        match ranges.last_mut() {
+            Some((_, end)) if start - *end < context => *end = offset,
+            _ => ranges.push((start, offset)),
+        }
+    }
+    if left.len() != right.len() {
+        match ranges.last_mut() {
+            Some((_, end)) if common - *end < context => *end = common,
+            _ => ranges.push((common, common)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let end_of = |data: &[u8]| if end == common { data.len() } else { end };
+            Hunk {
+                offset: start,
+                left_len: end_of(left) - start,
+                right_len: end_of(right) - start,
+            }
+        })
+        .collect()
+}
+
+/// the `ms` command turning `left` into `right` when they only differ by
+/// bytes added to or deleted from either end, as `gp`, `gs`, `ds` and `de` do
+pub fn equivalent_command(filename: &str, left: &[u8], right: &[u8]) -> Option<String> {
+    if left == right {
+        return None;
+    }
+    match Change::between(left, right) {
+        Change::GraftStart { added } => Some(format!("ms gp {filename} h:{}", hex::encode(added))),
+        Change::GraftEnd { added } => Some(format!("ms gs {filename} h:{}", hex::encode(added))),
+        Change::DeleteStart { removed } => Some(format!("ms ds {filename} {}", removed.len())),
+        Change::DeleteEnd { removed } => Some(format!("ms de {filename} {}", removed.len())),
+        Change::Overwrite { .. } | Change::Replace { .. } | Change::Created | Change::Removed { .. } => None,
+    }
+}
+
+/// the bytes of a hunk with `context` bytes on either side, eliding all
+/// but the first `DIFF_SHOWN` differing ones
+fn excerpt(data: &[u8], offset: usize, len: usize, context: usize) -> String {
+    let start = offset.saturating_sub(context).min(data.len());
+    let end = offset.min(data.len());
+    let shown = (offset + len.min(DIFF_SHOWN)).min(data.len());
+    let after = (offset + len).min(data.len());
+    let mut text = format!("{}[{}", hex::encode(&data[start..end]), hex::encode(&data[end..shown]));
+    if len > DIFF_SHOWN {
+        text.push_str(&format!("..+{}", len - DIFF_SHOWN));
+    }
+    text.push(']');
+    text.push_str(&hex::encode(&data[after..(after + context).min(data.len())]));
+    text
+}
+
+/// the differences between two files, one hunk per paragraph with its
+/// offset, lengths and hex context, the differing bytes being bracketed
+pub fn diff(left_name: &str, left: &[u8], right_name: &str, right: &[u8], context: usize) -> String {
+    if left == right {
+        return format!("{left_name} and {right_name} are identical");
+    }
+    let mut lines = vec![format!(
+        "--- {left_name} ({} bytes)\n+++ {right_name} ({} bytes)",
+        left.len(),
+        right.len()
+    )];
+    if let Some(command) = equivalent_command(left_name, left, right) {
+        lines.push(format!("same as: {command}"));
+        return lines.join("\n");
+    }
+    for hunk in hunks(left, right, context) {
+        lines.push(format!(
+            "@@ 0x{:08x} -{} +{} @@",
+            hunk.offset, hunk.left_len, hunk.right_len
+        ));
+        lines.push(format!("- {}", excerpt(left, hunk.offset, hunk.left_len, context)));
+        lines.push(format!("+ {}", excerpt(right, hunk.offset, hunk.right_len, context)));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        (0u8..64).collect()
+    }
+
+    #[test]
+    fn test_hunks() {
+        let mut right = test_data();
+        right[4] = 0xff;
+        right[5] = 0xff;
+        right[40] = 0xff;
+        assert_equal!(
+            hunks(&test_data(), &right, DIFF_CONTEXT),
+            vec![
+                Hunk { offset: 4, left_len: 2, right_len: 2 },
+                Hunk { offset: 40, left_len: 1, right_len: 1 },
+            ]
+        );
+        assert_equal!(
+            hunks(&test_data(), &right, 64),
+            vec![Hunk { offset: 4, left_len: 37, right_len: 37 }]
+        );
+        assert_equal!(
+            hunks(&test_data(), &right[..50], 0),
+            vec![
+                Hunk { offset: 4, left_len: 2, right_len: 2 },
+                Hunk { offset: 40, left_len: 1, right_len: 1 },
+                Hunk { offset: 50, left_len: 14, right_len: 0 },
+            ]
+        );
+        assert_equal!(hunks(&test_data(), &test_data(), DIFF_CONTEXT), vec![]);
+    }
+
+    #[test]
+    fn test_equivalent_command() {
+        let data = test_data();
+        let prefixed = [b"PK".to_vec(), data.clone()].concat();
+        let suffixed = [data.clone(), vec![0x00, 0x01]].concat();
+        assert_equal!(
+            equivalent_command("a.bin", &data, &prefixed),
+            Some("ms gp a.bin h:504b".to_string())
+        );
+        assert_equal!(
+            equivalent_command("a.bin", &data, &suffixed),
+            Some("ms gs a.bin h:0001".to_string())
+        );
+        assert_equal!(equivalent_command("a.bin", &prefixed, &data), Some("ms ds a.bin 2".to_string()));
+        assert_equal!(equivalent_command("a.bin", &suffixed, &data), Some("ms de a.bin 2".to_string()));
+        assert_equal!(equivalent_command("a.bin", &data, &data), None);
+        assert_equal!(equivalent_command("a.bin", &data, &data[1..63]), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let data = test_data();
+        let mut right = data.clone();
+        right[20] = 0xff;
+        assert_equal!(
+            diff("a.bin", &data, "b.bin", &right, 2),
+            [
+                "--- a.bin (64 bytes)",
+                "+++ b.bin (64 bytes)",
+                "@@ 0x00000014 -1 +1 @@",
+                "- 1213[14]1516",
+                "+ 1213[ff]1516",
+            ]
+            .join("\n")
+        );
+        assert_equal!(diff("a.bin", &data, "b.bin", &data, 2), "a.bin and b.bin are identical");
+    }
+}
@@ -0,0 +1,346 @@
+#[cfg(feature = "cli")]
+use base64::Engine;
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use crate::ram::{Layout, ZIP2};
+
+pub mod diff;
+pub use diff::*;
+
+pub const HEXDUMP_WIDTH: usize = 16;
+pub const C_ARRAY_WIDTH: usize = 12;
+/// how many of the first and last bytes are shown by `preview`
+pub const PREVIEW_SIZE: usize = 16;
+/// colour codes used to highlight the fields of an enchanted header
+pub const FIELD_COLOURS: [u8; 12] = [33, 240, 39, 240, 220, 208, 112, 113, 114, 160, 208, 250];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum Format {
+    /// space-separated 0x.. bytes
+    #[default]
+    List,
+    /// offsets, hex bytes and an ascii gutter
+    Hexdump,
+    /// the bytes themselves
+    Raw,
+    /// a C array declaration
+    CArray,
+    /// standard base64
+    #[cfg(feature = "cli")]
+    Base64,
+    /// a JSON array of integers
+    Json,
+}
+
+pub fn list(data: &[u8]) -> String {
+    data.iter()
+        .map(|x| format!("0x{:02x}", x))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+/// canonical hexdump, `offset` being the position of `data[0]` in its source
+pub fn hexdump(data: &[u8], offset: usize) -> String {
+    hexdump_painted(data, offset, |_, text| text.to_string())
+}
+
+/// hexdump whose hex and ascii cells are passed through `paint` along
+/// with the position of their byte in the source, e.g.: to colour them
+pub fn hexdump_painted<F>(data: &[u8], offset: usize, paint: F) -> String
+where
+    F: Fn(usize, &str) -> String,
+{
+    let mut lines = Vec::<String>::new();
+    for (index, chunk) in data.chunks(HEXDUMP_WIDTH).enumerate() {
+        let start = offset + index * HEXDUMP_WIDTH;
+        let mut hex = String::new();
+        for column in 0..HEXDUMP_WIDTH {
+            if column == HEXDUMP_WIDTH / 2 {
+                hex.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => {
+                    hex.push_str(&paint(start + column, &format!("{:02x}", byte)));
+                    hex.push(' ');
+                }
+                None => hex.push_str("   "),
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .enumerate()
+            .map(|(column, b)| paint(start + column, &printable(*b).to_string()))
+            .collect();
+        lines.push(format!("{:08x}  {} |{}|", start, hex, ascii));
+    }
+    lines.push(format!("{:08x}", offset + data.len()));
+    lines.join("\n")
+}
+
+/// the start and end of `len` bytes from `offset`, or up to the end when
+/// `len` is `None`, clamped to `size` bytes
+pub fn dump_range(size: usize, offset: usize, len: Option<usize>) -> (usize, usize) {
+    let start = offset.min(size);
+    match len {
+        Some(len) => (start, start.saturating_add(len).min(size)),
+        None => (start, size),
+    }
+}
+
+/// `text` in the bold colour of the `index`th field of a header
+#[cfg(feature = "cli")]
+pub fn paint_field(index: usize, text: &str) -> String {
+    ansi_term::Colour::Fixed(FIELD_COLOURS[index % FIELD_COLOURS.len()])
+        .bold()
+        .paint(text)
+        .to_string()
+}
+
+/// hexdump of the bytes `dump_range` selects from `contents` followed, when
+/// they are enchanted, by the offsets of each field of their header; the
+/// bytes and names of each field are passed through `paint` along with the
+/// index of the field, e.g.: `paint_field`
+pub fn dump<F>(contents: &[u8], offset: usize, len: Option<usize>, paint: F) -> String
+where
+    F: Fn(usize, &str) -> String,
+{
+    let (start, end) = dump_range(contents.len(), offset, len);
+    let layout = match Layout::parse(contents) {
+        Ok(layout) => layout,
+        Err(_) => return hexdump(&contents[start..end], start),
+    };
+    let fields = layout.fields();
+    let mut lines = vec![hexdump_painted(&contents[start..end], start, |offset, text| {
+        match fields.iter().position(|field| field.contains(offset)) {
+            Some(index) => paint(index, text),
+            None => text.to_string(),
+        }
+    })];
+    lines.push(String::new());
+    for (index, field) in fields.iter().enumerate() {
+        lines.push(format!("{:08x}-{:08x} {}", field.offset, field.end(), paint(index, field.name)));
+    }
+    lines.join("\n")
+}
+
+pub fn c_array(data: &[u8], name: &str) -> String {
+    let mut lines = Vec::<String>::new();
+    lines.push(format!("unsigned char {}[] = {{", name));
+    let rows: Vec<String> = data
+        .chunks(C_ARRAY_WIDTH)
+        .map(|chunk| {
+            format!(
+                "  {}",
+                chunk
+                    .iter()
+                    .map(|x| format!("0x{:02x}", x))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        })
+        .collect();
+    if !rows.is_empty() {
+        lines.push(rows.join(",\n"));
+    }
+    lines.push("};".to_string());
+    lines.push(format!("unsigned int {}_len = {};", name, data.len()));
+    lines.join("\n")
+}
+
+#[cfg(feature = "cli")]
+pub fn base64(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+pub fn json(data: &[u8]) -> String {
+    format!("[{}]", data.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(","))
+}
+
+/// summary of how an operation would turn `before` into `after`: the size
+/// change, the first and last bytes and the CRC-32/BZIP2 of both
+pub fn preview(before: &[u8], after: &[u8]) -> String {
+    let first = |data: &[u8]| hex::encode(&data[..data.len().min(PREVIEW_SIZE)]);
+    let last = |data: &[u8]| hex::encode(&data[data.len().saturating_sub(PREVIEW_SIZE)..]);
+    [
+        format!(
+            "size   {} -> {} ({:+})",
+            before.len(),
+            after.len(),
+            after.len() as i128 - before.len() as i128
+        ),
+        format!("first  {}", first(before)),
+        format!("    -> {}", first(after)),
+        format!("last   {}", last(before)),
+        format!("    -> {}", last(after)),
+        format!("crc32  {:08x} -> {:08x}", ZIP2.checksum(before), ZIP2.checksum(after)),
+    ]
+    .join("\n")
+}
+
+/// renders `data` in the given format, ready to be written to stdout
+pub fn render(data: &[u8], format: Format) -> Vec<u8> {
+    let text = match format {
+        Format::Raw => return data.to_vec(),
+        Format::List => list(data),
+        Format::Hexdump => hexdump(data, 0),
+        Format::CArray => c_array(data, "data"),
+        #[cfg(feature = "cli")]
+        Format::Base64 => base64(data),
+        Format::Json => json(data),
+    };
+    format!("{}\n", text).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        Vec::from([
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00,
+        ])
+    }
+
+    #[test]
+    fn test_list() {
+        assert_equal!(list(&[0x89, 0x50, 0x4e]), "0x89 0x50 0x4e");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        assert_equal!(
+            hexdump(&test_data(), 0),
+            [
+                "00000000  89 50 4e 47 0d 0a 1a 0a  00 00 00 0d 49 48 44 52  |.PNG........IHDR|",
+                "00000010  00 00                                             |..|",
+                "00000012",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_hexdump_offset() {
+        assert_equal!(
+            hexdump(&[0x50, 0x4b], 0x20),
+            [
+                "00000020  50 4b                                             |PK|",
+                "00000022",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_hexdump_painted() {
+        assert_equal!(
+            hexdump_painted(&[0x50, 0x4b], 0x20, |offset, text| if offset == 0x21 {
+                format!("[{}]", text)
+            } else {
+                text.to_string()
+            }),
+            [
+                "00000020  50 [4b]                                             |P[K]|",
+                "00000022",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_dump_range() {
+        assert_equal!(dump_range(10, 2, Some(4)), (2, 6));
+        assert_equal!(dump_range(10, 2, Some(usize::MAX)), (2, 10));
+        assert_equal!(dump_range(10, 20, None), (10, 10));
+    }
+
+    #[test]
+    fn test_dump() -> Result<(), crate::errors::MSError> {
+        // Given bytes that are not enchanted
+        // Then their dump should be a plain hexdump of the selected range
+        assert_equal!(
+            dump(&test_data(), 0x10, None, |_, text| format!("[{}]", text)),
+            hexdump(&test_data()[0x10..], 0x10)
+        );
+
+        // Given enchanted bytes
+        let enchanted = crate::ram::MetaMagic::new(test_data().repeat(3), "THISISMAGICO")?.enchant()?;
+        let fields = Layout::parse(&enchanted)?.fields();
+
+        // When I dump their first two bytes
+        let dumped = dump(&enchanted, 0, Some(2), |index, text| format!("{}[{}]", index, text));
+
+        // Then each byte should be painted with the index of its field
+        let lines: Vec<&str> = dumped.lines().collect();
+        assert_equal!(lines[0].starts_with("00000000  0["), true);
+
+        // And every field should be listed with its offsets
+        assert_equal!(lines.len(), 3 + fields.len());
+        assert_equal!(
+            lines[3].to_string(),
+            format!("{:08x}-{:08x} 0[{}]", fields[0].offset, fields[0].end(), fields[0].name)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_c_array() {
+        assert_equal!(
+            c_array(&test_data()[..14], "data"),
+            [
+                "unsigned char data[] = {",
+                "  0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d,",
+                "  0x49, 0x48",
+                "};",
+                "unsigned int data_len = 14;",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_base64() {
+        assert_equal!(base64(&test_data()[..4]), "iVBORw==");
+    }
+
+    #[test]
+    fn test_json() {
+        assert_equal!(json(&test_data()[..4]), "[137,80,78,71]");
+    }
+
+    #[test]
+    fn test_render_raw() {
+        assert_equal!(render(&test_data(), Format::Raw), test_data());
+        assert_equal!(render(&[0x0a], Format::List), b"0x0a\n".to_vec());
+    }
+
+    #[test]
+    fn test_preview() {
+        let before: Vec<u8> = (0u8..20).collect();
+        let after: Vec<u8> = (2u8..20).collect();
+        assert_equal!(
+            preview(&before, &after),
+            [
+                "size   20 -> 18 (-2)",
+                "first  000102030405060708090a0b0c0d0e0f",
+                "    -> 02030405060708090a0b0c0d0e0f1011",
+                "last   0405060708090a0b0c0d0e0f10111213",
+                "    -> 0405060708090a0b0c0d0e0f10111213",
+                "crc32  9af4a144 -> 93c55a0b",
+            ]
+            .join("\n")
+        );
+    }
+}
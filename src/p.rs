@@ -9,10 +9,127 @@ pub fn str_to_u128(s: &str) -> Result<u128, MSError> {
     } else if s.starts_with("0b") || s.starts_with(r"\b") {
         u128::from_str_radix(&s[2..], 2)?
     } else {
-        u128::from_str_radix(s, 10)?
+        s.parse::<u128>()?
     })
 }
 
+/// `str_to_u128` failing with `MSError::InvalidLiteral` for values that
+/// do not fit in a `usize`, e.g.: offsets and lengths
+pub fn str_to_usize(s: &str) -> Result<usize, MSError> {
+    match usize::try_from(str_to_u128(s)?) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(MSError::InvalidLiteral(format!("{s} is too large"))),
+    }
+}
+
+pub fn str_to_i128(s: &str) -> Result<i128, MSError> {
+    match s.strip_prefix('-') {
+        Some(magnitude) => match 0i128.checked_sub_unsigned(str_to_u128(magnitude)?) {
+            Some(value) => Ok(value),
+            None => Err(MSError::InvalidLiteral(format!("{s} does not fit in 128 bits"))),
+        },
+        None => match i128::try_from(str_to_u128(s)?) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(MSError::InvalidLiteral(format!("{s} does not fit in 128 bits"))),
+        },
+    }
+}
+
+/// big-endian bytes of `value` without leading zeros (at least one byte)
+pub fn u128_to_bytes(value: u128) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(bytes.len() - 1);
+    bytes[skip..].to_vec()
+}
+
+fn parse_width(kind: &str) -> Result<(bool, usize, bool), MSError> {
+    let (signed, rest) = match kind.chars().next() {
+        Some('u') => (false, &kind[1..]),
+        Some('i') => (true, &kind[1..]),
+        _ => return Err(MSError::InvalidLiteral(format!("unknown literal type: {kind}"))),
+    };
+    let (bits, little) = if let Some(bits) = rest.strip_suffix("le") {
+        (bits, Some(true))
+    } else if let Some(bits) = rest.strip_suffix("be") {
+        (bits, Some(false))
+    } else {
+        (rest, None)
+    };
+    let width = match bits {
+        "8" => 1,
+        "16" => 2,
+        "32" => 4,
+        "64" => 8,
+        "128" => 16,
+        _ => return Err(MSError::InvalidLiteral(format!("unknown literal type: {kind}"))),
+    };
+    let little = match (width, little) {
+        (1, little) => little.unwrap_or(false),
+        (_, Some(little)) => little,
+        (_, None) => {
+            return Err(MSError::InvalidLiteral(format!(
+                "{kind} requires an endianness suffix, e.g.: {kind}le or {kind}be"
+            )))
+        }
+    };
+    Ok((signed, width, little))
+}
+
+fn int_to_bytes(kind: &str, value: &str) -> Result<Vec<u8>, MSError> {
+    let (signed, width, little) = parse_width(kind)?;
+    let bits = (width * 8) as u32;
+    let be = if signed {
+        let value = str_to_i128(value)?;
+        let fits = bits == 128 || (value >= -(1i128 << (bits - 1)) && value < (1i128 << (bits - 1)));
+        if !fits {
+            return Err(MSError::InvalidLiteral(format!("{value} does not fit in {kind}")));
+        }
+        value.to_be_bytes()
+    } else {
+        let value = str_to_u128(value)?;
+        if bits < 128 && value >> bits != 0 {
+            return Err(MSError::InvalidLiteral(format!("{value} does not fit in {kind}")));
+        }
+        value.to_be_bytes()
+    };
+    let mut bytes = be[16 - width..].to_vec();
+    if little {
+        bytes.reverse();
+    }
+    Ok(bytes)
+}
+
+/// parses a byte literal as accepted by the graft commands:
+///
+/// - `0x100`, `0o44`, `0b101`, `37`: the number in big-endian with
+///   as many bytes as needed
+/// - `u16le:0x1234`, `u32be:7`, `i64le:-1`, `u8:255`: fixed-width
+///   integers (endianness is mandatory above 8 bits)
+/// - `s:"PK"`: the raw bytes of a string (quotes are optional)
+/// - `h:deadbeef`: a hex blob
+pub fn str_to_bytes(s: &str) -> Result<Vec<u8>, MSError> {
+    match s.split_once(':') {
+        Some(("s", text)) => {
+            let text = text
+                .strip_prefix('"')
+                .and_then(|t| t.strip_suffix('"'))
+                .unwrap_or(text);
+            Ok(text.as_bytes().to_vec())
+        }
+        Some(("h", blob)) => Ok(hex::decode(blob.strip_prefix("0x").unwrap_or(blob))?),
+        Some((kind, value)) => int_to_bytes(kind, value),
+        None => Ok(u128_to_bytes(str_to_u128(s)?)),
+    }
+}
+
+pub fn strs_to_bytes(literals: &[String]) -> Result<Vec<u8>, MSError> {
+    let mut bytes = Vec::<u8>::new();
+    for s in literals {
+        bytes.extend(str_to_bytes(s)?);
+    }
+    Ok(bytes)
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -28,4 +145,63 @@ mod tests {
         assert_equal!(str_to_u128("0b1010")?, 10u128);
         Ok(())
     }
+
+    #[test]
+    fn test_str_to_usize() -> Result<(), MSError> {
+        assert_equal!(str_to_usize("0x20")?, 32usize);
+        assert_equal!(
+            matches!(str_to_usize(&u128::MAX.to_string()), Err(MSError::InvalidLiteral(_))),
+            true
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_str_to_i128() -> Result<(), MSError> {
+        assert_equal!(str_to_i128("-1")?, -1i128);
+        assert_equal!(str_to_i128("-0x10")?, -16i128);
+        assert_equal!(str_to_i128("42")?, 42i128);
+        assert_equal!(str_to_i128(&format!("-{}", 1u128 << 127))?, i128::MIN);
+        assert_equal!(str_to_i128(&format!("{}", 1u128 << 127)).is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_str_to_bytes_bare_numbers() -> Result<(), MSError> {
+        assert_equal!(str_to_bytes("0x4f")?, vec![0x4f]);
+        assert_equal!(str_to_bytes("0x100")?, vec![0x01, 0x00]);
+        assert_equal!(str_to_bytes("0")?, vec![0x00]);
+        assert_equal!(str_to_bytes("0o44")?, vec![0x24]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_str_to_bytes_typed_integers() -> Result<(), MSError> {
+        assert_equal!(str_to_bytes("u16le:0x1234")?, vec![0x34, 0x12]);
+        assert_equal!(str_to_bytes("u16be:0x1234")?, vec![0x12, 0x34]);
+        assert_equal!(str_to_bytes("u32be:7")?, vec![0x00, 0x00, 0x00, 0x07]);
+        assert_equal!(str_to_bytes("i64le:-1")?, vec![0xff; 8]);
+        assert_equal!(str_to_bytes("i16be:-2")?, vec![0xff, 0xfe]);
+        assert_equal!(str_to_bytes("u8:255")?, vec![0xff]);
+        assert_equal!(str_to_bytes("i8:-128")?, vec![0x80]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_str_to_bytes_rejects_invalid_integers() {
+        assert_equal!(str_to_bytes("u8:256").is_err(), true);
+        assert_equal!(str_to_bytes("i8:128").is_err(), true);
+        assert_equal!(str_to_bytes("u16:1").is_err(), true);
+        assert_equal!(str_to_bytes("u24le:1").is_err(), true);
+        assert_equal!(str_to_bytes("f32le:1").is_err(), true);
+    }
+
+    #[test]
+    fn test_str_to_bytes_strings_and_blobs() -> Result<(), MSError> {
+        assert_equal!(str_to_bytes("s:\"PK\"")?, vec![0x50, 0x4b]);
+        assert_equal!(str_to_bytes("s:PK")?, vec![0x50, 0x4b]);
+        assert_equal!(str_to_bytes("h:deadbeef")?, vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_equal!(str_to_bytes("h:abc").is_err(), true);
+        Ok(())
+    }
 }
@@ -0,0 +1,106 @@
+use crate::errors::MSError;
+use crate::ram::crc32;
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use crc::{Crc, CRC_32_ISO_HDLC, CRC_64_XZ};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+pub const HDLC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+pub const XZ64: Crc<u64> = Crc::<u64>::new(&CRC_64_XZ);
+
+/// the algorithm of the odigest, ldigest and rdigest of a header; the
+/// discriminants are stored in the header so they must never change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[repr(u8)]
+pub enum DigestAlgorithm {
+    /// CRC-32/BZIP2, the only algorithm of headers without extensions
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "crc32-bzip2"))]
+    Crc32Bzip2 = 0,
+    /// CRC-32/ISO-HDLC, as used by zip, png and gzip
+    #[cfg_attr(feature = "cli", value(name = "crc32"))]
+    Crc32IsoHdlc = 1,
+    /// CRC-64/XZ
+    #[cfg_attr(feature = "cli", value(name = "crc64"))]
+    Crc64 = 2,
+    /// SHA-256
+    #[cfg_attr(feature = "cli", value(name = "sha256"))]
+    Sha256 = 3,
+}
+
+impl DigestAlgorithm {
+    pub fn all() -> Vec<DigestAlgorithm> {
+        vec![
+            DigestAlgorithm::Crc32Bzip2,
+            DigestAlgorithm::Crc32IsoHdlc,
+            DigestAlgorithm::Crc64,
+            DigestAlgorithm::Sha256,
+        ]
+    }
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_id(id: u8) -> Result<DigestAlgorithm, MSError> {
+        match DigestAlgorithm::all().into_iter().find(|a| a.id() == id) {
+            Some(algorithm) => Ok(algorithm),
+            None => Err(MSError::InvalidHeader(format!("unknown digest algorithm: {id}"))),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Crc32Bzip2 => "crc32-bzip2",
+            DigestAlgorithm::Crc32IsoHdlc => "crc32",
+            DigestAlgorithm::Crc64 => "crc64",
+            DigestAlgorithm::Sha256 => "sha256",
+        }
+    }
+    /// the length in bytes of the digests computed by this algorithm
+    pub fn size(&self) -> usize {
+        match self {
+            DigestAlgorithm::Crc32Bzip2 | DigestAlgorithm::Crc32IsoHdlc => 4,
+            DigestAlgorithm::Crc64 => 8,
+            DigestAlgorithm::Sha256 => 32,
+        }
+    }
+    pub fn digest(&self, data: &[u8]) -> Result<Vec<u8>, MSError> {
+        Ok(match self {
+            DigestAlgorithm::Crc32Bzip2 => crc32(data)?,
+            DigestAlgorithm::Crc32IsoHdlc => HDLC.checksum(data).to_be_bytes().to_vec(),
+            DigestAlgorithm::Crc64 => XZ64.checksum(data).to_be_bytes().to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_digests() -> Result<(), MSError> {
+        let data = b"123456789";
+        assert_equal!(hex::encode(DigestAlgorithm::Crc32Bzip2.digest(data)?), "fc891918");
+        assert_equal!(hex::encode(DigestAlgorithm::Crc32IsoHdlc.digest(data)?), "cbf43926");
+        assert_equal!(hex::encode(DigestAlgorithm::Crc64.digest(data)?), "995dc9bbdf1939fa");
+        assert_equal!(
+            hex::encode(DigestAlgorithm::Sha256.digest(data)?),
+            "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest_sizes_and_ids() -> Result<(), MSError> {
+        for algorithm in DigestAlgorithm::all() {
+            assert_equal!(algorithm.digest(b"")?.len(), algorithm.size());
+            assert_equal!(DigestAlgorithm::from_id(algorithm.id())?, algorithm);
+        }
+        assert_equal!(DigestAlgorithm::from_id(0xff).is_err(), true);
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::errors::MSError;
+use crate::ram::{
+    enchant_bytes_in_place, restore_bytes_in_place, DigestAlgorithm, EnchantOptions, HeaderPlacement, MetaMagic, Region,
+    SpellKind,
+};
+
+/// where an `Enchanter` keeps the header
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EnchantMode {
+    /// before the scrambled bytes, the output growing by the size of the header
+    #[default]
+    Prepend,
+    /// in a region of the input which must be all zeros, the output keeping
+    /// the size of the input
+    Reserved(Region),
+}
+
+/// enchants and restores bytes in memory with the given magic and
+/// options, e.g.:
+/// `Enchanter::new("MAGIC").car_size(64).digest(DigestAlgorithm::Sha256).enchant(&data)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enchanter {
+    magic: String,
+    options: EnchantOptions,
+    mode: EnchantMode,
+}
+
+impl Enchanter {
+    pub fn new(magic: &str) -> Enchanter {
+        Enchanter {
+            magic: magic.to_string(),
+            options: EnchantOptions::default(),
+            mode: EnchantMode::default(),
+        }
+    }
+    /// moves the first `size` bytes into the header instead of `CAR_SIZE`
+    pub fn car_size(self, size: usize) -> Enchanter {
+        self.region(Region::Range(0, size))
+    }
+    /// moves this region into the header instead of the first `CAR_SIZE` bytes
+    pub fn region(mut self, region: Region) -> Enchanter {
+        self.options.region = Some(region);
+        self
+    }
+    pub fn digest(mut self, algorithm: DigestAlgorithm) -> Enchanter {
+        self.options.digest = algorithm;
+        self
+    }
+    pub fn spell(mut self, spell: SpellKind) -> Enchanter {
+        self.options.spell = spell;
+        self
+    }
+    pub fn mode(mut self, mode: EnchantMode) -> Enchanter {
+        self.mode = mode;
+        self
+    }
+    /// authenticates header and body with an HMAC keyed from a content key
+    /// kept in a key slot, see `EnchantOptions::mac`
+    pub fn mac(mut self, mac: bool) -> Enchanter {
+        self.options.mac = mac;
+        self
+    }
+    /// records the type detected from the first bytes of the input
+    pub fn file_type(mut self, file_type: bool) -> Enchanter {
+        self.options.file_type = file_type;
+        self
+    }
+    /// records the original name of the input
+    pub fn file_name(mut self, name: &str) -> Enchanter {
+        self.options.file_name = Some(name.to_string());
+        self
+    }
+    /// lets another magic restore the output as well
+    pub fn recipient(mut self, magic: &str) -> Enchanter {
+        self.options.recipients.push(magic.to_string());
+        self
+    }
+    pub fn options(&self) -> &EnchantOptions {
+        &self.options
+    }
+    pub fn enchant(&self, input: &[u8]) -> Result<Vec<u8>, MSError> {
+        match &self.mode {
+            EnchantMode::Prepend => MetaMagic::with_options(input.to_vec(), &self.magic, &self.options)?.enchant(),
+            EnchantMode::Reserved(region) => {
+                let placement = HeaderPlacement::Reserved(*region);
+                Ok(enchant_bytes_in_place(input, &self.magic, &self.options, &placement)?.0)
+            }
+        }
+    }
+    pub fn restore(&self, input: &[u8]) -> Result<Vec<u8>, MSError> {
+        match &self.mode {
+            EnchantMode::Prepend => MetaMagic::from_enchanted(input.to_vec(), &self.magic)?.orig(),
+            EnchantMode::Reserved(region) => {
+                let placement = HeaderPlacement::Reserved(*region);
+                restore_bytes_in_place(input, None, &self.magic, &placement)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram::reverse_slice;
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        let mut data: Vec<u8> = (1u8..=100).collect();
+        data.extend([0x00; 156]);
+        data
+    }
+
+    #[test]
+    fn test_enchanter_defaults_to_meta_magic() -> Result<(), MSError> {
+        let enchanted = Enchanter::new("THISISMAGICO").enchant(&test_data())?;
+        assert_equal!(
+            MetaMagic::from_enchanted(enchanted, "THISISMAGICO")?.orig()?,
+            test_data()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_enchanter_with_options() -> Result<(), MSError> {
+        // Given an enchanter moving 64 bytes into a header with a sha256 digest
+        let enchanter = Enchanter::new("THISISMAGICO")
+            .car_size(64)
+            .digest(DigestAlgorithm::Sha256)
+            .mac(true)
+            .recipient("OTHERMAGIC");
+
+        // When I enchant some bytes
+        let enchanted = enchanter.enchant(&test_data())?;
+
+        // Then they should be restored by either magic
+        let meta = MetaMagic::from_enchanted(enchanted.clone(), "OTHERMAGIC")?;
+        assert_equal!(meta.range()?, (0, 64));
+        assert_equal!(meta.mac().is_some(), true);
+        assert_equal!(enchanter.restore(&enchanted)?, test_data());
+
+        // And not by another one
+        assert_equal!(
+            Enchanter::new("NOTTHEMAGICO").restore(&enchanted).is_err(),
+            true
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_enchanter_in_reserved_mode() -> Result<(), MSError> {
+        let enchanter = Enchanter::new("THISISMAGICO").mode(EnchantMode::Reserved(Region::Tail(128)));
+        let enchanted = enchanter.enchant(&test_data())?;
+        assert_equal!(enchanted.len(), test_data().len());
+        assert_equal!(enchanted[..32], reverse_slice(&test_data()[..32]));
+        assert_equal!(enchanter.restore(&enchanted)?, test_data());
+        Ok(())
+    }
+}
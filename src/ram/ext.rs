@@ -0,0 +1,147 @@
+use crate::errors::MSError;
+use crate::pad::unpad32;
+use crate::ram::usize_to_hex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// size of the tag and length preceding the value of each record
+pub const RECORD_HEAD_SIZE: usize = 5;
+
+pub const EXT_FILE_TYPE: u8 = 0x01;
+pub const EXT_FILE_NAME: u8 = 0x02;
+pub const EXT_DIGEST: u8 = 0x03;
+pub const EXT_MAC: u8 = 0x04;
+pub const EXT_KEY_SLOTS: u8 = 0x05;
+pub const EXT_LAYER: u8 = 0x06;
+pub const EXT_RANGE: u8 = 0x07;
+pub const EXT_SPELL: u8 = 0x08;
+
+pub fn ext_name(tag: u8) -> &'static str {
+    match tag {
+        EXT_FILE_TYPE => "file_type",
+        EXT_FILE_NAME => "file_name",
+        EXT_DIGEST => "digest",
+        EXT_MAC => "mac",
+        EXT_KEY_SLOTS => "key_slots",
+        EXT_LAYER => "layer",
+        EXT_RANGE => "range",
+        EXT_SPELL => "spell",
+        _ => "ext",
+    }
+}
+
+/// tagged records stored in the header of enchanted files, each encoded
+/// as its tag, a 32 bits length and the value itself
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Extensions {
+    records: Vec<(u8, Vec<u8>)>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    pub fn tags(&self) -> Vec<u8> {
+        self.records.iter().map(|(tag, _)| *tag).collect()
+    }
+    pub fn get(&self, tag: u8) -> Option<Vec<u8>> {
+        self.records
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, value)| value.clone())
+    }
+    /// replaces the value of `tag`, keeping its position if already present
+    pub fn set(&mut self, tag: u8, value: Vec<u8>) {
+        match self.records.iter_mut().find(|(t, _)| *t == tag) {
+            Some(record) => record.1 = value,
+            None => self.records.push((tag, value)),
+        }
+    }
+    pub fn remove(&mut self, tag: u8) -> Option<Vec<u8>> {
+        let index = self.records.iter().position(|(t, _)| *t == tag)?;
+        Some(self.records.remove(index).1)
+    }
+    pub fn encode(&self) -> Result<Vec<u8>, MSError> {
+        let mut encoded = Vec::<u8>::new();
+        for (tag, value) in &self.records {
+            encoded.push(*tag);
+            encoded.extend(usize_to_hex(value.len())?);
+            encoded.extend(value);
+        }
+        Ok(encoded)
+    }
+    pub fn decode(input: &[u8]) -> Result<Extensions, MSError> {
+        let mut extensions = Extensions::new();
+        for (tag, value) in records(input)? {
+            extensions.records.push((tag, value.to_vec()));
+        }
+        Ok(extensions)
+    }
+}
+
+/// splits encoded records into (tag, value) without copying
+pub fn records(input: &[u8]) -> Result<Vec<(u8, &[u8])>, MSError> {
+    let mut found = Vec::<(u8, &[u8])>::new();
+    let mut offset: usize = 0;
+    while offset < input.len() {
+        if offset + RECORD_HEAD_SIZE > input.len() {
+            return Err(MSError::InvalidHeader(format!(
+                "truncated extension record at offset {offset}"
+            )));
+        }
+        let tag = input[offset];
+        let length = unpad32(input[offset + 1..offset + RECORD_HEAD_SIZE].to_vec())[0] as usize;
+        let start = offset + RECORD_HEAD_SIZE;
+        if start + length > input.len() {
+            return Err(MSError::InvalidHeader(format!(
+                "{} extension needs {length} bytes at offset {start} but has {}",
+                ext_name(tag),
+                input.len() - start
+            )));
+        }
+        found.push((tag, &input[start..start + length]));
+        offset = start + length;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_extensions_roundtrip() -> Result<(), MSError> {
+        let mut ext = Extensions::new();
+        assert_equal!(ext.is_empty(), true);
+        assert_equal!(ext.encode()?, Vec::<u8>::new());
+
+        ext.set(EXT_FILE_TYPE, vec![0x01]);
+        ext.set(EXT_FILE_NAME, b"a.png".to_vec());
+        ext.set(EXT_FILE_TYPE, vec![0x02]);
+        assert_equal!(ext.tags(), vec![EXT_FILE_TYPE, EXT_FILE_NAME]);
+        assert_equal!(
+            hex::encode(ext.encode()?),
+            "0100000001020200000005612e706e67"
+        );
+        assert_equal!(Extensions::decode(&ext.encode()?)?, ext);
+
+        assert_equal!(ext.remove(EXT_FILE_TYPE), Some(vec![0x02]));
+        assert_equal!(ext.get(EXT_FILE_TYPE), None);
+        assert_equal!(ext.get(EXT_FILE_NAME), Some(b"a.png".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extensions_truncated() {
+        assert_equal!(Extensions::decode(&[0x01, 0x00, 0x00]).is_err(), true);
+        assert_equal!(
+            Extensions::decode(&[0x01, 0x00, 0x00, 0x00, 0x02, 0xff]).is_err(),
+            true
+        );
+    }
+}
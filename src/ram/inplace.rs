@@ -0,0 +1,133 @@
+use crate::errors::MSError;
+use crate::ram::{EnchantOptions, MetaMagic, Region};
+
+/// where a file enchanted in place keeps its header
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderPlacement {
+    /// in a sidecar file next to it, see `crate::fs::sidecar_name`
+    Sidecar,
+    /// in a region of the file itself, which must be all zeros
+    Reserved(Region),
+}
+
+/// the offset of the reserved region of `input`, failing with
+/// `MSError::ReservedRegion` unless it is all zeros, holds `header_size`
+/// bytes and is apart from the region scrambled in place
+pub fn reserved_offset(
+    input: &[u8],
+    reserved: &Region,
+    header_size: usize,
+    scrambled: (usize, usize),
+) -> Result<usize, MSError> {
+    let (offset, len) = reserved.resolve(input.len())?;
+    let (start, scrambled_len) = scrambled;
+    if header_size > len {
+        return Err(MSError::ReservedRegion(format!(
+            "the header needs {header_size} bytes but only {len} are reserved"
+        )));
+    }
+    if offset < start + scrambled_len && start < offset + len {
+        return Err(MSError::ReservedRegion(format!(
+            "{offset}:{len} overlaps the scrambled region {start}:{scrambled_len}"
+        )));
+    }
+    if input[offset..offset + len].iter().any(|byte| *byte != 0) {
+        return Err(MSError::ReservedRegion(format!("{offset}:{len} is not all zeros")));
+    }
+    Ok(offset)
+}
+
+/// the contents of a file enchanted in place and of its sidecar, which
+/// only exists when `placement` is `HeaderPlacement::Sidecar`
+pub fn enchant_bytes_in_place(
+    input: &[u8],
+    magic: &str,
+    options: &EnchantOptions,
+    placement: &HeaderPlacement,
+) -> Result<(Vec<u8>, Option<Vec<u8>>), MSError> {
+    let meta = MetaMagic::with_options(input.to_vec(), magic, options)?;
+    let header = meta.head()?;
+    let mut output = meta.scrambled()?;
+    match placement {
+        HeaderPlacement::Sidecar => Ok((output, Some(header))),
+        HeaderPlacement::Reserved(reserved) => {
+            let offset = reserved_offset(input, reserved, header.len(), meta.range()?)?;
+            output[offset..offset + header.len()].copy_from_slice(&header);
+            Ok((output, None))
+        }
+    }
+}
+
+/// the original contents of a file enchanted in place, reading the header
+/// from `sidecar` or from the reserved region as `placement` says
+pub fn restore_bytes_in_place(
+    input: &[u8],
+    sidecar: Option<&[u8]>,
+    magic: &str,
+    placement: &HeaderPlacement,
+) -> Result<Vec<u8>, MSError> {
+    let mut scrambled = input.to_vec();
+    let header = match (placement, sidecar) {
+        (HeaderPlacement::Sidecar, Some(sidecar)) => sidecar.to_vec(),
+        (HeaderPlacement::Sidecar, None) => {
+            return Err(MSError::InvalidHeader("the header is in a missing sidecar".to_string()))
+        }
+        (HeaderPlacement::Reserved(reserved), _) => {
+            let (offset, len) = reserved.resolve(scrambled.len())?;
+            let header = scrambled[offset..offset + len].to_vec();
+            scrambled[offset..offset + len].fill(0);
+            header
+        }
+    };
+    MetaMagic::from_detached(&header, &scrambled, magic)?.orig()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        let mut data: Vec<u8> = (1u8..=100).collect();
+        data.extend([0x00; 156]);
+        data
+    }
+
+    #[test]
+    fn test_enchant_and_restore_bytes_in_place() -> Result<(), MSError> {
+        let options = EnchantOptions::default();
+        let reserved = HeaderPlacement::Reserved(Region::Tail(128));
+        for placement in [HeaderPlacement::Sidecar, reserved] {
+            let (output, sidecar) = enchant_bytes_in_place(&test_data(), "THISISMAGICO", &options, &placement)?;
+            assert_equal!(output.len(), test_data().len());
+            assert_equal!(sidecar.is_some(), placement == HeaderPlacement::Sidecar);
+            assert_equal!(
+                restore_bytes_in_place(&output, sidecar.as_deref(), "THISISMAGICO", &placement)?,
+                test_data()
+            );
+        }
+        assert_equal!(
+            restore_bytes_in_place(&test_data(), None, "THISISMAGICO", &HeaderPlacement::Sidecar).is_err(),
+            true
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_offset() {
+        let data = test_data();
+        assert_equal!(reserved_offset(&data, &Region::Tail(128), 100, (0, 32)), Ok(128));
+        assert_equal!(
+            reserved_offset(&data, &Region::Tail(128), 129, (0, 32)).is_err(),
+            true
+        );
+        assert_equal!(
+            reserved_offset(&data, &Region::Range(16, 100), 50, (0, 32)).is_err(),
+            true
+        );
+        assert_equal!(
+            reserved_offset(&data, &Region::Range(90, 100), 50, (0, 32)).is_err(),
+            true
+        );
+    }
+}
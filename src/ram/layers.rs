@@ -0,0 +1,135 @@
+use crate::errors::MSError;
+use crate::pad::unpad32;
+use crate::ram::ext::{Extensions, EXT_LAYER};
+use crate::ram::{Layout, MetaMagic, SIZE_SIZE};
+
+/// how many enchantments wrap `input`: 0 when it is not enchanted, else
+/// the depth recorded in its header, which is only recorded above 1;
+/// fails with `MSError::InvalidHeader` when that record is malformed
+pub fn layer_depth(input: &[u8]) -> Result<usize, MSError> {
+    let layout = match Layout::parse(input) {
+        Ok(layout) => layout,
+        Err(_) => return Ok(0),
+    };
+    match layout.extensions(input) {
+        Ok(ext) => recorded_depth(&ext),
+        Err(_) => Ok(0),
+    }
+}
+
+/// the depth held by a layer record, which is `SIZE_SIZE` bytes and at least 1
+pub fn decode_depth(value: &[u8]) -> Result<usize, MSError> {
+    if value.len() != SIZE_SIZE {
+        return Err(MSError::InvalidHeader(format!(
+            "layer needs {SIZE_SIZE} bytes but has {}",
+            value.len()
+        )));
+    }
+    match unpad32(value.to_vec())[0] as usize {
+        0 => Err(MSError::InvalidHeader("layer must be at least 1".to_string())),
+        depth => Ok(depth),
+    }
+}
+
+/// the depth recorded in the extensions of an enchanted header
+pub fn recorded_depth(ext: &Extensions) -> Result<usize, MSError> {
+    match ext.get(EXT_LAYER) {
+        Some(value) => decode_depth(&value),
+        None => Ok(1),
+    }
+}
+
+/// restores every layer of `input`, outermost first, with the magic at
+/// the same position in `magics`; the last magic is reused for the layers
+/// beyond the end of `magics`
+pub fn unwind(input: Vec<u8>, magics: &[String]) -> Result<Vec<u8>, MSError> {
+    let mut current = input;
+    let depth = layer_depth(&current)?;
+    for (index, layer) in (1..=depth).rev().enumerate() {
+        let magic = match magics.get(index).or(magics.last()) {
+            Some(magic) => magic,
+            None => {
+                return Err(MSError::LayerFailed(layer, Box::new(MSError::MagicMismatch)));
+            }
+        };
+        current = match MetaMagic::from_enchanted(current, magic).and_then(|meta| meta.orig()) {
+            Ok(orig) => orig,
+            Err(e) => return Err(MSError::LayerFailed(layer, Box::new(e))),
+        };
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        (0u8..80).collect()
+    }
+
+    fn enchant_layers(magics: &[&str]) -> Result<Vec<u8>, MSError> {
+        let mut current = test_data();
+        for magic in magics {
+            current = MetaMagic::new(current, magic)?.enchant()?;
+        }
+        Ok(current)
+    }
+
+    #[test]
+    fn test_layer_depth() -> Result<(), MSError> {
+        assert_equal!(layer_depth(&test_data())?, 0);
+        assert_equal!(layer_depth(&enchant_layers(&["A"])?)?, 1);
+        assert_equal!(layer_depth(&enchant_layers(&["A", "B", "C"])?)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_layer_record() -> Result<(), MSError> {
+        for value in [vec![0x00; 9], vec![0x01], vec![0x00; SIZE_SIZE]] {
+            // Given a file whose layer record was tampered with
+            let mut meta = MetaMagic::from_enchanted(enchant_layers(&["A", "B"])?, "B")?;
+            meta.ext.set(EXT_LAYER, value);
+            let tampered = meta.enchant()?;
+
+            // Then reading its depth or enchanting it again should fail
+            assert_equal!(matches!(layer_depth(&tampered), Err(MSError::InvalidHeader(_))), true);
+            assert_equal!(matches!(MetaMagic::new(tampered.clone(), "C"), Err(MSError::InvalidHeader(_))), true);
+
+            // And describing its layout should not panic
+            let layout = Layout::parse(&tampered)?;
+            assert_equal!(layout.field("layer").is_some(), true);
+            for field in layout.fields() {
+                field.describe(&tampered);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwind() -> Result<(), MSError> {
+        let enchanted = enchant_layers(&["A", "B", "C"])?;
+        let magics = vec!["C".to_string(), "B".to_string(), "A".to_string()];
+        assert_equal!(unwind(enchanted.clone(), &magics)?, test_data());
+        assert_equal!(unwind(test_data(), &magics)?, test_data());
+
+        let wrong = vec!["C".to_string(), "A".to_string(), "B".to_string()];
+        assert_equal!(
+            unwind(enchanted, &wrong),
+            Err(MSError::LayerFailed(2, Box::new(MSError::MagicMismatch)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwind_reuses_the_last_magic() -> Result<(), MSError> {
+        let enchanted = enchant_layers(&["A", "A", "B"])?;
+        assert_equal!(unwind(enchanted.clone(), &["B".to_string(), "A".to_string()])?, test_data());
+        assert_equal!(
+            unwind(enchanted, &[]),
+            Err(MSError::LayerFailed(3, Box::new(MSError::MagicMismatch)))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use crate::errors::MSError;
+use crate::pad::unpad32;
+use crate::ram::ext::{ext_name, records, Extensions, EXT_DIGEST, EXT_RANGE, RECORD_HEAD_SIZE};
+use crate::ram::{decode_depth, decode_range, getmark, DigestAlgorithm, SpellKind, CAR_SIZE, DIGEST_SIZE, SLOT_SIZE};
+use crate::sig::FileType;
+
+pub const SIZE_SIZE: usize = 4;
+pub const MAGIC_SIZE_SUFFIX: u8 = 0x3d;
+pub const TAIL_SIZE_SUFFIX: u8 = 0x24;
+/// replaces `TAIL_SIZE_SUFFIX` when the header carries extensions
+pub const TAIL_SIZE_SUFFIX_EXT: u8 = 0x25;
+/// how many bytes of the body are shown by `Field::describe`
+pub const DESCRIBE_LIMIT: usize = 16;
+
+/// a named region of an enchanted file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Field {
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+    pub fn contains(&self, offset: usize) -> bool {
+        offset >= self.offset && offset < self.end()
+    }
+    pub fn slice<'a>(&self, input: &'a [u8]) -> &'a [u8] {
+        &input[self.offset..self.end()]
+    }
+    /// human-readable value of this field within `input`
+    pub fn describe(&self, input: &[u8]) -> String {
+        let bytes = self.slice(input);
+        match self.name {
+            "magic_size" | "tail_size" => format!("{}", unpad32(bytes.to_vec())[0]),
+            "magic" => match std::str::from_utf8(bytes) {
+                Ok(magic) => format!("{:?}", magic),
+                Err(_) => hex::encode(bytes),
+            },
+            "range" => match decode_range(&bytes[RECORD_HEAD_SIZE..]) {
+                Ok((start, len)) => format!("{start}:{len}"),
+                Err(_) => hex::encode(&bytes[RECORD_HEAD_SIZE..]),
+            },
+            "layer" => match decode_depth(&bytes[RECORD_HEAD_SIZE..]) {
+                Ok(depth) => format!("{depth}"),
+                Err(_) => hex::encode(&bytes[RECORD_HEAD_SIZE..]),
+            },
+            "ext_size" => format!("{}", unpad32(bytes.to_vec())[0]),
+            "file_type" => match bytes[RECORD_HEAD_SIZE..].first().and_then(|id| FileType::from_id(*id)) {
+                Some(t) => t.name().to_string(),
+                None => hex::encode(&bytes[RECORD_HEAD_SIZE..]),
+            },
+            "digest" => match bytes[RECORD_HEAD_SIZE..].first().map(|id| DigestAlgorithm::from_id(*id)) {
+                Some(Ok(algorithm)) => algorithm.name().to_string(),
+                _ => hex::encode(&bytes[RECORD_HEAD_SIZE..]),
+            },
+            "spell" => match bytes[RECORD_HEAD_SIZE..].first().map(|id| SpellKind::from_id(*id)) {
+                Some(Ok(spell)) => spell.name().to_string(),
+                _ => hex::encode(&bytes[RECORD_HEAD_SIZE..]),
+            },
+            "key_slots" => format!("{} slots", (bytes.len() - RECORD_HEAD_SIZE) / SLOT_SIZE),
+            "file_name" => format!("{:?}", String::from_utf8_lossy(&bytes[RECORD_HEAD_SIZE..])),
+            "cdr" if bytes.len() > DESCRIBE_LIMIT => {
+                format!("{}...", hex::encode(&bytes[..DESCRIBE_LIMIT]))
+            }
+            _ => hex::encode(bytes),
+        }
+    }
+}
+
+/// the regions of an enchanted file, in the order they appear in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    fields: Vec<Field>,
+}
+
+impl Layout {
+    /// parses the header of `input` without knowing its magic, failing
+    /// with `MSError::InvalidHeader` when `input` is not enchanted
+    pub fn parse(input: &[u8]) -> Result<Layout, MSError> {
+        let (mut cursor, tail_size) = Layout::parse_fields(input)?;
+        let cdr = cursor.take("cdr", tail_size)?;
+        if cdr.end() != input.len() {
+            return Err(MSError::InvalidHeader(format!(
+                "expected {} bytes after the header but found {}",
+                tail_size,
+                input.len() - cdr.offset
+            )));
+        }
+        Ok(Layout { fields: cursor.fields })
+    }
+    /// parses a header kept apart from its body, ignoring whatever
+    /// follows the header in `input`
+    pub fn parse_header(input: &[u8]) -> Result<Layout, MSError> {
+        let (cursor, _) = Layout::parse_fields(input)?;
+        Ok(Layout { fields: cursor.fields })
+    }
+    /// the fields up to machf and the size of the body that follows them
+    fn parse_fields(input: &[u8]) -> Result<(Cursor<'_>, usize), MSError> {
+        let mut cursor = Cursor { input, offset: 0, fields: Vec::new() };
+        let magic_size = unpad32(cursor.take("magic_size", SIZE_SIZE)?.slice(input).to_vec())[0];
+        expect_byte(input, &cursor.take("0x3d", 1)?, MAGIC_SIZE_SUFFIX)?;
+        let tail_size = unpad32(cursor.take("tail_size", SIZE_SIZE)?.slice(input).to_vec())[0];
+        let mut algorithm = DigestAlgorithm::default();
+        let mut car_size = CAR_SIZE;
+        if input.get(cursor.offset) == Some(&TAIL_SIZE_SUFFIX_EXT) {
+            cursor.take("0x25", 1)?;
+            let ext_size = unpad32(cursor.take("ext_size", SIZE_SIZE)?.slice(input).to_vec())[0];
+            let ext_end = cursor.offset.saturating_add(ext_size as usize);
+            let ext = input.get(cursor.offset..ext_end).ok_or_else(|| {
+                MSError::InvalidHeader(format!("extensions need {ext_size} bytes"))
+            })?;
+            for (tag, value) in records(ext)? {
+                cursor.take(ext_name(tag), RECORD_HEAD_SIZE + value.len())?;
+                if tag == EXT_DIGEST {
+                    algorithm = DigestAlgorithm::from_id(*value.first().unwrap_or(&0xff))?;
+                }
+                if tag == EXT_RANGE {
+                    car_size = decode_range(value)?.1;
+                }
+            }
+        } else {
+            expect_byte(input, &cursor.take("0x24", 1)?, TAIL_SIZE_SUFFIX)?;
+        }
+        cursor.take("magic", usize::try_from(magic_size).unwrap_or(usize::MAX))?;
+        expect_mark(input, &cursor.take("mach0", DIGEST_SIZE)?)?;
+        cursor.take("odigest", algorithm.size())?;
+        cursor.take("ldigest", algorithm.size())?;
+        cursor.take("rdigest", algorithm.size())?;
+        cursor.take("car", car_size)?;
+        expect_mark(input, &cursor.take("machf", DIGEST_SIZE)?)?;
+        Ok((cursor, usize::try_from(tail_size).unwrap_or(usize::MAX)))
+    }
+    pub fn fields(&self) -> Vec<Field> {
+        self.fields.clone()
+    }
+    pub fn field(&self, name: &str) -> Option<Field> {
+        self.fields.iter().find(|f| f.name == name).cloned()
+    }
+    pub fn field_at(&self, offset: usize) -> Option<Field> {
+        self.fields.iter().find(|f| f.contains(offset)).cloned()
+    }
+    /// the bytes of the named field, empty if there is no such field
+    pub fn get<'a>(&self, input: &'a [u8], name: &str) -> &'a [u8] {
+        match self.field(name) {
+            Some(field) => field.slice(input),
+            None => &[],
+        }
+    }
+    /// one line per field with its offset, length, name and value
+    pub fn table(&self, input: &[u8]) -> String {
+        let mut lines = vec![format!("{:<10} {:<10} {:<10} value", "offset", "length", "name")];
+        for field in &self.fields {
+            lines.push(format!(
+                "{:<10} {:<10} {:<10} {}",
+                format!("0x{:08x}", field.offset),
+                field.length,
+                field.name,
+                field.describe(input)
+            ));
+        }
+        lines.join("\n")
+    }
+    /// decodes the extension records of the header, if any
+    pub fn extensions(&self, input: &[u8]) -> Result<Extensions, MSError> {
+        match (self.field("ext_size"), self.field("magic")) {
+            (Some(ext_size), Some(magic)) => Extensions::decode(&input[ext_size.end()..magic.offset]),
+            _ => Ok(Extensions::new()),
+        }
+    }
+    pub fn header_size(&self) -> usize {
+        match self.fields.last() {
+            Some(cdr) if cdr.name == "cdr" => cdr.offset,
+            Some(machf) => machf.end(),
+            None => 0,
+        }
+    }
+}
+
+/// walks through the input recording each field taken from it
+struct Cursor<'a> {
+    input: &'a [u8],
+    offset: usize,
+    fields: Vec<Field>,
+}
+
+impl Cursor<'_> {
+    fn take(&mut self, name: &'static str, length: usize) -> Result<Field, MSError> {
+        if self.offset.saturating_add(length) > self.input.len() {
+            return Err(MSError::InvalidHeader(format!(
+                "{name} needs {length} bytes at offset {} but input has {}",
+                self.offset,
+                self.input.len()
+            )));
+        }
+        let field = Field { name, offset: self.offset, length };
+        self.offset += length;
+        self.fields.push(field.clone());
+        Ok(field)
+    }
+}
+
+fn expect_byte(input: &[u8], field: &Field, expected: u8) -> Result<(), MSError> {
+    match field.slice(input) {
+        [byte] if *byte == expected => Ok(()),
+        found => Err(MSError::InvalidHeader(format!(
+            "expected 0x{expected:02x} at offset {} but found {}",
+            field.offset,
+            hex::encode(found)
+        ))),
+    }
+}
+
+fn expect_mark(input: &[u8], field: &Field) -> Result<(), MSError> {
+    let found = field.slice(input);
+    if found == getmark().as_slice() {
+        Ok(())
+    } else {
+        Err(MSError::InvalidHeader(format!(
+            "expected {} mark at offset {} but found {}",
+            field.name,
+            field.offset,
+            hex::encode(found)
+        )))
+    }
+}
+
+pub fn is_enchanted(input: &[u8]) -> bool {
+    Layout::parse(input).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram::{EnchantOptions, MetaMagic, Region, EXT_FILE_NAME};
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::from([
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52,
+        ]);
+        data.extend((0u8..50).collect::<Vec<u8>>());
+        data
+    }
+
+    #[test]
+    fn test_layout_fields() -> Result<(), MSError> {
+        let enchanted = MetaMagic::new(test_data(), "THISISMAGICO")?.enchant()?;
+        let layout = Layout::parse(&enchanted)?;
+        assert_equal!(
+            layout
+                .fields()
+                .iter()
+                .map(|f| (f.name, f.offset, f.length))
+                .collect::<Vec<_>>(),
+            vec![
+                ("magic_size", 0, 4),
+                ("0x3d", 4, 1),
+                ("tail_size", 5, 4),
+                ("0x24", 9, 1),
+                ("magic", 10, 12),
+                ("mach0", 22, 4),
+                ("odigest", 26, 4),
+                ("ldigest", 30, 4),
+                ("rdigest", 34, 4),
+                ("car", 38, 32),
+                ("machf", 70, 4),
+                ("cdr", 74, 34),
+            ]
+        );
+        assert_equal!(layout.header_size(), 74);
+        assert_equal!(layout.get(&enchanted, "magic"), b"THISISMAGICO");
+        assert_equal!(layout.field_at(40).map(|f| f.name), Some("car"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_extensions() -> Result<(), MSError> {
+        let options = EnchantOptions {
+            file_type: true,
+            file_name: Some("a.png".to_string()),
+            ..EnchantOptions::default()
+        };
+        let enchanted = MetaMagic::with_options(test_data(), "THISISMAGICO", &options)?.enchant()?;
+        let layout = Layout::parse(&enchanted)?;
+        assert_equal!(
+            layout.fields()[3..8]
+                .iter()
+                .map(|f| (f.name, f.offset, f.length))
+                .collect::<Vec<_>>(),
+            vec![
+                ("0x25", 9, 1),
+                ("ext_size", 10, 4),
+                ("file_type", 14, 6),
+                ("file_name", 20, 10),
+                ("magic", 30, 12),
+            ]
+        );
+        assert_equal!(layout.extensions(&enchanted)?.get(EXT_FILE_NAME), Some(b"a.png".to_vec()));
+        assert_equal!(
+            layout.field("file_type").map(|f| f.describe(&enchanted)),
+            Some("png".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_range() -> Result<(), MSError> {
+        let options = EnchantOptions {
+            region: Some(Region::Tail(10)),
+            ..EnchantOptions::default()
+        };
+        let enchanted = MetaMagic::with_options(test_data(), "THISISMAGICO", &options)?.enchant()?;
+        let layout = Layout::parse(&enchanted)?;
+        assert_equal!(layout.field("car").map(|f| f.length), Some(10));
+        assert_equal!(layout.field("cdr").map(|f| f.length), Some(56));
+        assert_equal!(
+            layout.field("range").map(|f| f.describe(&enchanted)),
+            Some("56:10".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_table() -> Result<(), MSError> {
+        let enchanted = MetaMagic::new(test_data(), "THISISMAGICO")?.enchant()?;
+        let table = Layout::parse(&enchanted)?.table(&enchanted);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_equal!(lines.len(), 13);
+        assert_equal!(lines[0], "offset     length     name       value");
+        assert_equal!(lines[1], "0x00000000 4          magic_size 12");
+        assert_equal!(lines[2], "0x00000004 1          0x3d       3d");
+        assert_equal!(lines[3], "0x00000005 4          tail_size  34");
+        assert_equal!(lines[5], "0x0000000a 12         magic      \"THISISMAGICO\"");
+        assert_equal!(lines[6], "0x00000016 4          mach0      c3bec3bf");
+        assert_equal!(
+            lines[12],
+            "0x0000004a 34         cdr        31302f2e2d2c2b2a2928272625242322..."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_parse_header() -> Result<(), MSError> {
+        let meta = MetaMagic::new(test_data(), "THISISMAGICO")?;
+        let mut header = meta.head()?;
+        header.extend([0x00; 8]);
+        let layout = Layout::parse_header(&header)?;
+        assert_equal!(layout.fields().last().map(|f| f.name), Some("machf"));
+        assert_equal!(layout.header_size(), 74);
+        assert_equal!(Layout::parse(&header).is_err(), true);
+        assert_equal!(Layout::parse_header(&header[..73]).is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_rejects_plain_files() {
+        assert_equal!(is_enchanted(&test_data()), false);
+        assert_equal!(is_enchanted(&[]), false);
+    }
+
+    #[test]
+    fn test_layout_rejects_truncated_files() -> Result<(), MSError> {
+        let mut enchanted = MetaMagic::new(test_data(), "THISISMAGICO")?.enchant()?;
+        enchanted.pop();
+        assert_equal!(is_enchanted(&enchanted), false);
+        Ok(())
+    }
+}
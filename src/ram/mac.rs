@@ -0,0 +1,64 @@
+use crate::errors::MSError;
+use crate::ram::ext::RECORD_HEAD_SIZE;
+use crate::ram::Layout;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const MAC_SIZE: usize = 32;
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 of `data`
+pub fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// whether `tag` is the HMAC-SHA256 of `data`, compared in constant time
+pub fn verify_hmac(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+/// `input` with the value of its mac record zeroed, i.e.: the bytes
+/// that were authenticated when enchanting it
+pub fn unsigned(input: &[u8], layout: &Layout) -> Vec<u8> {
+    let mut unsigned = input.to_vec();
+    if let Some(field) = layout.field("mac") {
+        for byte in &mut unsigned[field.offset + RECORD_HEAD_SIZE..field.end()] {
+            *byte = 0;
+        }
+    }
+    unsigned
+}
+
+/// checks the mac of an enchanted file, failing with
+/// `MSError::AuthenticationFailed` when it has none or it was not computed
+/// with `key` over the same header and body
+pub fn authenticate(input: &[u8], layout: &Layout, key: &[u8]) -> Result<(), MSError> {
+    let field = match layout.field("mac") {
+        Some(field) => field,
+        None => return Err(MSError::AuthenticationFailed),
+    };
+    if verify_hmac(key, &unsigned(input, layout), &field.slice(input)[RECORD_HEAD_SIZE..]) {
+        Ok(())
+    } else {
+        Err(MSError::AuthenticationFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_hmac() {
+        // RFC 4231, test case 2
+        assert_equal!(
+            hex::encode(hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
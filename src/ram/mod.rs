@@ -1,44 +1,74 @@
 use crc::{Crc, CRC_32_BZIP2};
 use hex;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+pub mod digest;
+pub mod enchanter;
+pub mod ext;
+pub mod inplace;
+pub mod layers;
+pub mod layout;
+pub mod mac;
+pub mod region;
+pub mod slots;
+pub mod spell;
+pub mod stream;
 pub mod vecs;
+pub mod xform;
 use crate::errors::MSError;
+use crate::sig::{detect, FileType};
+pub use digest::*;
+pub use enchanter::*;
+pub use ext::*;
+pub use inplace::*;
+pub use layers::*;
+pub use layout::*;
+pub use mac::*;
+pub use region::*;
+pub use slots::*;
+pub use spell::*;
+pub use stream::*;
 pub use vecs::*;
+pub use xform::*;
 
-use crate::pad::{pad32, unpad32};
+use crate::pad::pad32;
 
 pub const CAR_SIZE: usize = 32;
+/// size of CRC-32 digests and of the marks around them
 pub const DIGEST_SIZE: usize = 4;
 pub const ZIP2: Crc<u32> = Crc::<u32>::new(&CRC_32_BZIP2);
-pub type Digest = [u8; DIGEST_SIZE];
-pub type Car = [u8; CAR_SIZE];
+/// as long as the `DigestAlgorithm` that computed it requires
+pub type Digest = Vec<u8>;
+pub type Mark = [u8; DIGEST_SIZE];
 
 
-pub fn digest_from_vec8(data: Vec<u8>) -> Result<Digest, Vec<u8>> {
+pub fn digest_from_vec8(data: Vec<u8>) -> Result<Mark, Vec<u8>> {
     let mut data = data.clone();
     while data.len() > DIGEST_SIZE {
-        if data[0] == 0 as u8 {
+        if data[0] == 0_u8 {
             data.remove(0);
         } else {
             break
         }
     }
-    Ok(<Digest>::try_from(data)?)
+    <Mark>::try_from(data)
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DigestMismatch {
+    name: String,
     expected: Digest,
     actual: Digest,
 }
 impl DigestMismatch {
-    pub fn new(expected: Digest, actual: Digest) -> DigestMismatch {
+    pub fn new(name: &str, expected: Digest, actual: Digest) -> DigestMismatch {
         DigestMismatch {
-            expected: expected.clone(),
-            actual: actual.clone(),
+            name: name.to_string(),
+            expected,
+            actual,
         }
     }
 }
@@ -47,34 +77,36 @@ impl fmt::Display for DigestMismatch {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "digest mismatch {:#?} != {:#?}",
-            self.expected, self.actual
+            "{} mismatch {} != {}",
+            self.name,
+            hex::encode(&self.expected),
+            hex::encode(&self.actual)
         )
     }
 }
 
 pub fn hexdecs(data: &str) -> Result<Vec<u8>, MSError> {
-    match hex::decode(&data) {
+    match hex::decode(data) {
         Ok(tocat) => Ok(tocat),
         Err(e) => Err(MSError::HexDecodingError(format!("Failed to decode hex: {data}: {e}"))),
     }
 }
 pub fn hexdeca(a: &[u8]) -> Result<Vec<u8>, MSError> {
-    Ok(hexdecs(&hex::encode(&a))?)
+    hexdecs(&hex::encode(a))
 }
 pub fn hexdecu32(value: u32) -> Result<Vec<u8>, MSError> {
     let padded = pad32(value as i64)?;
-    Ok(hexdeca(&padded)?)
+    hexdeca(&padded)
 }
 pub fn crc32(data: &[u8]) -> Result<Vec<u8>, MSError> {
-    hexdecu32(ZIP2.checksum(&data))
+    hexdecu32(ZIP2.checksum(data))
 }
 pub fn usize_to_hex(value: usize) -> Result<Vec<u8>, MSError> {
-    return Ok(pad32(value as i64)?);
+    pad32(value as i64)
 }
 pub fn hex_to_usize(input: Vec<u8>, limit: usize) -> Result<usize, MSError> {
     let bytes = Vec::from(&input[..limit]);
-    return Ok(bytes[0] as usize);
+    Ok(bytes[0] as usize)
 }
 
 pub fn reverse_slice(data: &[u8]) -> Vec<u8> {
@@ -84,109 +116,322 @@ pub fn reverse_slice(data: &[u8]) -> Vec<u8> {
 }
 
 pub fn getmark() -> Vec<u8> {
-    return hexdecs("c3bec3bf").unwrap();
+    hexdecs("c3bec3bf").unwrap()
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MetaMagic {
     tail_size: usize,    // arbitrary
     magic_size: usize,   // 12 (minimum)
     magic: Vec<u8>,      // 12 (presumed)
-    mach0: Digest,      // +4=16
-    odigest: Digest,    // +4=20
+    mach0: Mark,        // +4=16
+    odigest: Digest,    // +4=20 (or DigestAlgorithm::size())
     ldigest: Digest,    // +4=24
     rdigest: Digest,    // +4=28
-    car: Car, // +32= 60 // contains original magic numbers
-    machf: Digest,      // 64
+    car: Vec<u8>, // +32= 60 // contains original magic numbers (or the region)
+    machf: Mark,        // 64
     cdr: Vec<u8>,        //..tail_size
+    ext: Extensions,     // between tail size and magic, when not empty
+    #[cfg_attr(feature = "serde", serde(skip))]
+    key: Vec<u8>,        // content key wrapped by the key slots, if any
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct EnchantOptions {
+    /// records the type detected from the first bytes of the input
+    pub file_type: bool,
+    /// records the original name of the input
+    pub file_name: Option<String>,
+    /// computes odigest, ldigest and rdigest
+    pub digest: DigestAlgorithm,
+    /// authenticates header and body with an HMAC keyed from the content
+    /// key, moving the magic into a key slot if there are no recipients
+    pub mac: bool,
+    /// other magics that can restore the file, each wrapping a shared
+    /// content key in its own key slot along with the magic itself
+    pub recipients: Vec<String>,
+    /// moves this region into the header instead of the first `CAR_SIZE` bytes
+    pub region: Option<Region>,
+    /// scrambles the car and the cdr, reversing them by default
+    pub spell: SpellKind,
 }
 
 impl MetaMagic {
     pub fn new(input: Vec<u8>, magic: &str) -> Result<MetaMagic, MSError>  {
+        MetaMagic::with_options(input, magic, &EnchantOptions::default())
+    }
+    pub fn with_options(input: Vec<u8>, magic: &str, options: &EnchantOptions) -> Result<MetaMagic, MSError>  {
         let magic = magic.to_string();
         let bom = getmark();
-        let magic_size: usize = magic.len();
+        let algorithm = options.digest;
 
-        // let bom_size: usize = bom.len();
-        // let input_size = input.len();
+        let odigest = algorithm.digest(&input)?;
+        let (start, len) = options.region.unwrap_or_default().resolve(input.len())?;
+        let car = Vec::from(&input[start..start + len]);
+        let mut cdr = Vec::from(&input[..start]);
+        cdr.extend(&input[start + len..]);
 
-        let odigest = crc32(&input)?;
-        let car = Vec::from(&input[..CAR_SIZE]);
-        // assert_eq!(car.len(), CAR_SIZE);
+        let ldigest = algorithm.digest(&car)?;
+        let rdigest = algorithm.digest(&cdr)?;
 
-        let cdr = Vec::from(&input[CAR_SIZE..]);
-        // assert_eq!(cdr.len(), input_size - CAR_SIZE);
-
-        let ldigest = crc32(&car)?;
-        let rdigest = crc32(&cdr)?;
+        let mut ext = Extensions::new();
+        if options.file_type {
+            if let Some(file_type) = detect(&input) {
+                ext.set(EXT_FILE_TYPE, vec![file_type.id()]);
+            }
+        }
+        if let Some(file_name) = &options.file_name {
+            ext.set(EXT_FILE_NAME, file_name.as_bytes().to_vec());
+        }
+        if algorithm != DigestAlgorithm::default() {
+            ext.set(EXT_DIGEST, vec![algorithm.id()]);
+        }
+        if (start, len) != (0, CAR_SIZE) {
+            ext.set(EXT_RANGE, encode_range(start, len)?);
+        }
+        let depth = layer_depth(&input)?;
+        if depth > 0 {
+            ext.set(EXT_LAYER, usize_to_hex(depth + 1)?);
+        }
+        if options.spell != SpellKind::default() {
+            ext.set(EXT_SPELL, vec![options.spell.id()]);
+        }
+        let mut key = Vec::new();
+        let mut magic = magic.into_bytes();
+        // a mac or a spell keyed from a magic stored in the clear protects nothing
+        if !options.recipients.is_empty() || options.mac || options.spell.keyed() {
+            key = content_key()?;
+            let mut value = wrap(&key, &magic, options.mac)?;
+            for recipient in &options.recipients {
+                value.extend(wrap(&key, recipient.as_bytes(), options.mac)?);
+            }
+            ext.set(EXT_KEY_SLOTS, value);
+            magic = Vec::new();
+        }
+        if options.mac {
+            ext.set(EXT_MAC, vec![0; MAC_SIZE]);
+        }
+        let secret = if key.is_empty() { &magic } else { &key };
+        let spell = options.spell.spell();
+        let car = spell.apply(&car, secret)?;
+        let cdr = spell.apply(&cdr, secret)?;
 
-        return Ok(MetaMagic {
+        let mut meta = MetaMagic {
             tail_size: cdr.len(),
-            magic_size: magic_size,
-            magic: magic.into(),
+            magic_size: magic.len(),
+            magic,
             mach0: digest_from_vec8(bom.clone()).unwrap(),
-            odigest: digest_from_vec8(odigest).unwrap(),
-            ldigest: digest_from_vec8(ldigest).unwrap(),
-            rdigest: digest_from_vec8(rdigest).unwrap(),
-            car: <Car>::try_from(reverse_slice(&car)).unwrap(),
-            machf: <Digest>::try_from(bom).unwrap(),
-            cdr: reverse_slice(&cdr.clone()),
-        });
+            odigest,
+            ldigest,
+            rdigest,
+            car,
+            machf: <Mark>::try_from(bom).unwrap(),
+            cdr,
+            ext,
+            key,
+        };
+        if options.mac {
+            meta.sign()?;
+        }
+        Ok(meta)
+    }
+    /// computes the mac over the header, with a zeroed mac, and the body
+    fn sign(&mut self) -> Result<(), MSError> {
+        self.ext.set(EXT_MAC, vec![0; MAC_SIZE]);
+        let mac = hmac(&self.secret(), &self.enchant()?);
+        self.ext.set(EXT_MAC, mac);
+        Ok(())
+    }
+    /// signs again after changing the header, if it was signed
+    fn resign(&mut self) -> Result<(), MSError> {
+        match self.mac() {
+            Some(_) => self.sign(),
+            None => Ok(()),
+        }
+    }
+    /// the content key when the magic is kept in key slots, else the
+    /// magic: it keys the mac and the spell
+    fn secret(&self) -> Vec<u8> {
+        if self.key.is_empty() {
+            self.magic.clone()
+        } else {
+            self.key.clone()
+        }
+    }
+    /// how many magics can restore the file
+    pub fn key_slots(&self) -> usize {
+        match self.ext.get(EXT_KEY_SLOTS) {
+            Some(value) => value.len() / SLOT_SIZE,
+            None => 1,
+        }
+    }
+    /// lets `magic` restore the file as well, moving the current magic
+    /// into a key slot first if the file has none
+    pub fn add_magic(&mut self, magic: &str) -> Result<(), MSError> {
+        let mut value = match self.ext.get(EXT_KEY_SLOTS) {
+            Some(value) => value,
+            None => {
+                let secret = self.secret();
+                self.key = content_key()?;
+                let value = wrap(&self.key, &self.magic, self.mac().is_some())?;
+                self.magic = Vec::new();
+                self.magic_size = 0;
+                self.respell(&secret)?;
+                value
+            }
+        };
+        if unlock(&value, magic.as_bytes())?.is_none() {
+            value.extend(wrap(&self.key, magic.as_bytes(), self.mac().is_some())?);
+        }
+        self.ext.set(EXT_KEY_SLOTS, value);
+        self.resign()
+    }
+    /// replaces `old`, which must currently restore the file, with `new`
+    pub fn change_magic(&mut self, old: &str, new: &str) -> Result<(), MSError> {
+        match self.ext.get(EXT_KEY_SLOTS) {
+            Some(value) => {
+                let index = match unlock(&value, old.as_bytes())? {
+                    Some((index, _, _)) => index,
+                    None => return Err(MSError::MagicMismatch),
+                };
+                let mut slots: Vec<Vec<u8>> = slots(&value)?.into_iter().map(|s| s.to_vec()).collect();
+                slots[index] = wrap(&self.key, new.as_bytes(), self.mac().is_some())?;
+                self.ext.set(EXT_KEY_SLOTS, slots.concat());
+            }
+            None if self.magic != old.as_bytes() => return Err(MSError::MagicMismatch),
+            None => {
+                self.magic = new.as_bytes().to_vec();
+                self.magic_size = self.magic.len();
+                self.respell(old.as_bytes())?;
+            }
+        }
+        self.resign()
+    }
+    /// scrambles the car and the cdr again after the secret changed from
+    /// `old`, if the spell uses it
+    fn respell(&mut self, old: &[u8]) -> Result<(), MSError> {
+        let kind = self.spell()?;
+        if kind.keyed() {
+            let spell = kind.spell();
+            let secret = self.secret();
+            self.car = spell.apply(&spell.revert(&self.car, old)?, &secret)?;
+            self.cdr = spell.apply(&spell.revert(&self.cdr, old)?, &secret)?;
+        }
+        Ok(())
+    }
+    /// removes the key slot unwrapped by `magic`, which must not be the last one
+    pub fn remove_magic(&mut self, magic: &str) -> Result<(), MSError> {
+        let value = match self.ext.get(EXT_KEY_SLOTS) {
+            Some(value) => value,
+            None => return Err(MSError::KeySlotError("the file has no key slots".to_string())),
+        };
+        let index = match unlock(&value, magic.as_bytes())? {
+            Some((index, _, _)) => index,
+            None => return Err(MSError::MagicMismatch),
+        };
+        let remaining: Vec<&[u8]> = slots(&value)?
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, slot)| slot)
+            .collect();
+        if remaining.is_empty() {
+            return Err(MSError::KeySlotError("cannot remove the last key slot".to_string()));
+        }
+        self.ext.set(EXT_KEY_SLOTS, remaining.concat());
+        self.resign()
+    }
+    pub fn mac(&self) -> Option<Vec<u8>> {
+        self.ext.get(EXT_MAC)
+    }
+    /// the content key unwrapped by `magic` from the key slots of the
+    /// header, empty when it has none, and whether the file must carry a
+    /// mac, failing with `MSError::MagicMismatch` when `magic` cannot
+    /// restore the file
+    fn unlock_key(input: &[u8], layout: &Layout, magic: &str) -> Result<(Vec<u8>, bool), MSError> {
+        match layout.field("key_slots") {
+            Some(field) => match unlock(&field.slice(input)[RECORD_HEAD_SIZE..], magic.as_bytes())? {
+                Some((_, key, mac)) => Ok((key, mac)),
+                None => Err(MSError::MagicMismatch),
+            },
+            None if layout.get(input, "magic") != magic.as_bytes() => Err(MSError::MagicMismatch),
+            None => Ok((Vec::new(), false)),
+        }
     }
     pub fn from_enchanted(input: Vec<u8>, spell: &str) -> Result<MetaMagic, MSError>  {
-        let digest_size: usize = 4;
-        // let mut magic_size: i64 = magic.len() as i64;
-        // assert!(magic_size == 12);
-
-        let mut input = input.clone();
-        let magic_size = unpad32(Vec::from([input.remove(0),input.remove(0),input.remove(0),input.remove(0)]))[0];
-        let magic_suffix = hex_to_usize(Vec::from([input.remove(0)]), 1)?;
-        assert_eq!(magic_suffix, 0x3d);
-        let tail_size = unpad32(Vec::from([input.remove(0), input.remove(0), input.remove(0), input.remove(0)]))[0];
-
-        let tail_suffix = hex_to_usize(Vec::from([input.remove(0)]), 1)?;
-        assert_eq!(tail_suffix, 0x24);
-
-        let magic_size:usize = magic_size as usize;
-        let tail_size:usize = tail_size as usize;
-        let magic: Vec<u8> = Vec::from(&input[..magic_size]);
-        assert_eq!(&magic, spell.as_bytes());
-        let input = Vec::from(&input[magic_size..]);
-
-        let mach0: Vec<u8> = Vec::from(&input[..digest_size]);
-        let input = Vec::from(&input[digest_size..]);
-        let odigest: Vec<u8> = Vec::from(&input[..digest_size]);
-        let input = Vec::from(&input[digest_size..]);
-        let ldigest: Vec<u8> = Vec::from(&input[..digest_size]);
-        let input = Vec::from(&input[digest_size..]);
-        let rdigest: Vec<u8> = Vec::from(&input[..digest_size]);
-        let input = Vec::from(&input[digest_size..]);
-        let car: Vec<u8> = Vec::from(&input[..CAR_SIZE]);
-        let input = Vec::from(&input[CAR_SIZE..]);
-        let machf: Vec<u8> = Vec::from(&input[..digest_size]);
-        let input = Vec::from(&input[digest_size..]);
-
-        return Ok(MetaMagic {
-            tail_size: tail_size,
+        let layout = Layout::parse(&input)?;
+        let magic: Vec<u8> = layout.get(&input, "magic").to_vec();
+        let (key, mac) = MetaMagic::unlock_key(&input, &layout, spell)?;
+        if mac || layout.field("mac").is_some() {
+            authenticate(&input, &layout, if key.is_empty() { spell.as_bytes() } else { &key })?;
+        }
+        let tail_size = layout.get(&input, "cdr").len();
+
+        let meta = MetaMagic {
+            tail_size,
             magic_size: magic.len(),
-            magic: magic.into(),
-            mach0: <Digest>::try_from(mach0).unwrap(),
-            odigest: <Digest>::try_from(odigest).unwrap(),
-            ldigest: <Digest>::try_from(ldigest).unwrap(),
-            rdigest: <Digest>::try_from(rdigest).unwrap(),
-            car: <Car>::try_from(car).unwrap(),
-            machf: <Digest>::try_from(machf).unwrap(),
-            cdr: Vec::from(input),
-        });
+            magic,
+            mach0: <Mark>::try_from(layout.get(&input, "mach0")).unwrap(),
+            odigest: layout.get(&input, "odigest").to_vec(),
+            ldigest: layout.get(&input, "ldigest").to_vec(),
+            rdigest: layout.get(&input, "rdigest").to_vec(),
+            car: layout.get(&input, "car").to_vec(),
+            machf: <Mark>::try_from(layout.get(&input, "machf")).unwrap(),
+            cdr: layout.get(&input, "cdr").to_vec(),
+            ext: layout.extensions(&input)?,
+            key,
+        };
+        meta.verify()?;
+        Ok(meta)
+    }
+    pub fn digest_algorithm(&self) -> Result<DigestAlgorithm, MSError> {
+        match self.ext.get(EXT_DIGEST) {
+            Some(id) => DigestAlgorithm::from_id(*id.first().unwrap_or(&0xff)),
+            None => Ok(DigestAlgorithm::default()),
+        }
+    }
+    /// the spell scrambling the car and the cdr
+    pub fn spell(&self) -> Result<SpellKind, MSError> {
+        recorded_spell(&self.ext)
+    }
+    /// the start and length of the region moved into the header
+    pub fn range(&self) -> Result<(usize, usize), MSError> {
+        recorded_range(&self.ext)
+    }
+    /// checks the digests of the header against the original contents
+    pub fn verify(&self) -> Result<(), MSError> {
+        let (start, _) = self.range()?;
+        if start > self.cdr.len() {
+            return Err(MSError::InvalidHeader(format!(
+                "range starts at {start} but the body has {} bytes",
+                self.cdr.len()
+            )));
+        }
+        let algorithm = self.digest_algorithm()?;
+        let spell = self.spell()?.spell();
+        let car = spell.revert(&self.car, &self.secret())?;
+        let cdr = spell.revert(&self.cdr, &self.secret())?;
+        for (name, expected, data) in [
+            ("ldigest", self.ldigest(), car),
+            ("rdigest", self.rdigest(), cdr),
+            ("odigest", self.odigest(), self.orig()?),
+        ] {
+            let actual = algorithm.digest(&data)?;
+            if actual != expected {
+                return Err(MSError::DigestMismatch(DigestMismatch::new(name, expected, actual)));
+            }
+        }
+        Ok(())
     }
     pub fn magic(&self) -> Vec<u8> {
         self.magic.clone()
     }
     pub fn magic_size_hex(&self) -> Result<Vec<u8>, MSError> {
-        Ok(usize_to_hex(self.magic_size)?)
+        usize_to_hex(self.magic_size)
     }
     pub fn tail_size_hex(&self) -> Result<Vec<u8>, MSError> {
-        Ok(usize_to_hex(self.tail_size)?)
+        usize_to_hex(self.tail_size)
     }
     pub fn odigest(&self) -> Vec<u8> {
         self.odigest.clone().to_vec()
@@ -209,6 +454,21 @@ impl MetaMagic {
     pub fn cdr(&self) -> Vec<u8> {
         self.cdr.clone().to_vec()
     }
+    pub fn extensions(&self) -> Extensions {
+        self.ext.clone()
+    }
+    /// the type of the original file, if recorded when enchanting
+    pub fn file_type(&self) -> Option<FileType> {
+        FileType::from_id(*self.ext.get(EXT_FILE_TYPE)?.first()?)
+    }
+    /// how many enchantments wrap the original file, this one included
+    pub fn layer(&self) -> Result<usize, MSError> {
+        recorded_depth(&self.ext)
+    }
+    /// the name of the original file, if recorded when enchanting
+    pub fn file_name(&self) -> Option<String> {
+        Some(String::from_utf8_lossy(&self.ext.get(EXT_FILE_NAME)?).to_string())
+    }
     pub fn head(&self) -> Result<Vec<u8>, MSError> {
         let mut helmet: Vec<u8> = Vec::new();
         // magic size
@@ -216,7 +476,14 @@ impl MetaMagic {
         helmet.push(0x3d);              // magic size suffix/tail size prefix
         // tail size
         helmet.extend(self.tail_size_hex()?);
-        helmet.push(0x24);              // tail size suffix
+        if self.ext.is_empty() {
+            helmet.push(0x24);          // tail size suffix
+        } else {
+            let ext = self.ext.encode()?;
+            helmet.push(0x25);          // tail size suffix, extensions follow
+            helmet.extend(usize_to_hex(ext.len())?);
+            helmet.extend(ext);
+        }
         helmet.extend(&self.magic());   // Magic
         helmet.extend(&self.mach0());   // Mach0
         helmet.extend(&self.odigest()); // ODigest
@@ -227,15 +494,53 @@ impl MetaMagic {
         Ok(helmet)
     }
 
-    pub fn orig(&self) -> Vec<u8> {
+    pub fn orig(&self) -> Result<Vec<u8>, MSError> {
+        let (start, _) = self.range().unwrap_or((0, CAR_SIZE));
+        let spell = self.spell().unwrap_or_default().spell();
+        let cdr = spell.revert(&self.cdr, &self.secret())?;
+        let start = start.min(cdr.len());
         let mut realigned: Vec<u8> = Vec::new();
-        realigned.extend(&reverse_slice(&self.car()));
-        realigned.extend(&reverse_slice(&self.cdr()));
-        return realigned.clone();
+        realigned.extend(&cdr[..start]);
+        realigned.extend(&spell.revert(&self.car, &self.secret())?);
+        realigned.extend(&cdr[start..]);
+        Ok(realigned)
+    }
+
+    /// the original contents with only the region moved into the header
+    /// scrambled, as kept by files enchanted in place
+    pub fn scrambled(&self) -> Result<Vec<u8>, MSError> {
+        let (start, _) = self.range().unwrap_or((0, CAR_SIZE));
+        let spell = self.spell().unwrap_or_default().spell();
+        let cdr = spell.revert(&self.cdr, &self.secret())?;
+        let start = start.min(cdr.len());
+        let mut scrambled: Vec<u8> = Vec::new();
+        scrambled.extend(&cdr[..start]);
+        scrambled.extend(&self.car());
+        scrambled.extend(&cdr[start..]);
+        Ok(scrambled)
+    }
+    /// reads the header of a file enchanted in place, `header` being kept
+    /// apart from the `scrambled` contents, see `scrambled`
+    pub fn from_detached(header: &[u8], scrambled: &[u8], spell: &str) -> Result<MetaMagic, MSError> {
+        let layout = Layout::parse_header(header)?;
+        let (start, len) = recorded_range(&layout.extensions(header)?)?;
+        if start.saturating_add(len) > scrambled.len() {
+            return Err(MSError::InvalidHeader(format!(
+                "range {start}:{len} does not fit in {} bytes",
+                scrambled.len()
+            )));
+        }
+        let (key, _) = MetaMagic::unlock_key(header, &layout, spell)?;
+        let secret = if key.is_empty() { spell.as_bytes() } else { &key };
+        let mut cdr = scrambled[..start].to_vec();
+        cdr.extend(&scrambled[start + len..]);
+        let mut enchanted = header[..layout.header_size()].to_vec();
+        enchanted.extend(recorded_spell(&layout.extensions(header)?)?.spell().apply(&cdr, secret)?);
+        MetaMagic::from_enchanted(enchanted, spell)
     }
 
     pub fn body(&self) -> Vec<u8> {
-        return self.cdr.clone();
+        self.cdr.clone()
     }
 
     pub fn enchant(&self) -> Result<Vec<u8>, MSError> {
@@ -336,7 +641,7 @@ mod tests {
         assert_equal!(meta.tail_size, 50);
         assert_equal!(
             hex::encode(meta.magic()),
-            hex::encode(magic.as_bytes().to_vec())
+            hex::encode(magic.as_bytes())
         );
         assert_equal!(&hex::encode(meta.magic()), "5448495349534d414749434f");
         assert_equal!(&hex::encode(meta.mach0()), "c3bec3bf");
@@ -350,7 +655,7 @@ mod tests {
         assert_equal!(meta.car(), reverse_slice(&original[..32]));
         assert_equal!(
             &hex::encode(meta.car()),
-            &hex::encode(&reverse_slice(&original[..32]))
+            &hex::encode(reverse_slice(&original[..32]))
         );
         assert_equal!(
             &hex::encode(meta.car()),
@@ -359,7 +664,7 @@ mod tests {
         assert_equal!(meta.cdr(), reverse_slice(&original[32..]));
         assert_equal!(
             &hex::encode(meta.cdr()),
-            &hex::encode(&reverse_slice(&original[32..]))
+            &hex::encode(reverse_slice(&original[32..]))
         );
         assert_equal!(&hex::encode(meta.cdr()), "826042ae444e454900000000a66471f401000200000060639908544144490a000000c81bc4a7ffffff45544c5003000000bb");
         assert_equal!(&hex::encode(meta.machf()), "c3bec3bf");
@@ -424,6 +729,300 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_metamagic_extensions() -> Result<(), MSError> {
+        let magic = String::from("THISISMAGICO");
+        let options = EnchantOptions {
+            file_type: true,
+            file_name: Some("test.png".to_string()),
+            ..EnchantOptions::default()
+        };
+
+        let meta0 = MetaMagic::with_options(test_data(), &magic, &options)?;
+        assert_equal!(meta0.file_type(), Some(FileType::Png));
+        assert_equal!(meta0.file_name(), Some("test.png".to_string()));
+        assert_equal!(
+            &hex::encode(&meta0.head()?[..33]),
+            "0000000c3d0000003225000000130100000001010200000008746573742e706e67"
+        );
+
+        let meta1 = MetaMagic::from_enchanted(meta0.enchant()?, &magic)?;
+        assert_equal!(meta1, meta0);
+        assert_equal!(meta1.file_type(), Some(FileType::Png));
+        assert_equal!(meta1.orig()?, test_data());
+
+        let plain = MetaMagic::with_options(test_data(), &magic, &EnchantOptions::default())?;
+        assert_equal!(plain.file_type(), None);
+        assert_equal!(plain.file_name(), None);
+        assert_equal!(plain.enchant()?, MetaMagic::new(test_data(), &magic)?.enchant()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_digest_algorithms() -> Result<(), MSError> {
+        let magic = String::from("THISISMAGICO");
+        for algorithm in DigestAlgorithm::all() {
+            let options = EnchantOptions {
+                digest: algorithm,
+                ..EnchantOptions::default()
+            };
+            let meta0 = MetaMagic::with_options(test_data(), &magic, &options)?;
+            assert_equal!(meta0.digest_algorithm()?, algorithm);
+            assert_equal!(meta0.odigest(), algorithm.digest(&test_data())?);
+            assert_equal!(meta0.ldigest().len(), algorithm.size());
+
+            let meta1 = MetaMagic::from_enchanted(meta0.enchant()?, &magic)?;
+            assert_equal!(meta1, meta0);
+            assert_equal!(meta1.orig()?, test_data());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_detects_tampering() -> Result<(), MSError> {
+        let magic = String::from("THISISMAGICO");
+        let options = EnchantOptions {
+            digest: DigestAlgorithm::Sha256,
+            ..EnchantOptions::default()
+        };
+        let mut enchanted = MetaMagic::with_options(test_data(), &magic, &options)?.enchant()?;
+        let last = enchanted.len() - 1;
+        enchanted[last] ^= 0xff;
+        match MetaMagic::from_enchanted(enchanted, &magic) {
+            Err(MSError::DigestMismatch(_)) => {}
+            other => panic!("expected a digest mismatch, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_mac() -> Result<(), MSError> {
+        let magic = String::from("THISISMAGICO");
+        let options = EnchantOptions {
+            mac: true,
+            ..EnchantOptions::default()
+        };
+        let meta0 = MetaMagic::with_options(test_data(), &magic, &options)?;
+        let enchanted = meta0.enchant()?;
+        let layout = Layout::parse(&enchanted)?;
+        assert_equal!(
+            meta0.mac(),
+            Some(hmac(&meta0.key, &unsigned(&enchanted, &layout)))
+        );
+
+        // the mac is not keyed from anything stored in the clear
+        assert_equal!(layout.get(&enchanted, "magic").len(), 0);
+        assert_equal!(meta0.key_slots(), 1);
+
+        let meta1 = MetaMagic::from_enchanted(enchanted.clone(), &magic)?;
+        assert_equal!(meta1, meta0);
+
+        // the body and its digests were tampered with consistently
+        let mut tampered = MetaMagic::new(reverse_slice(&test_data()), &magic)?;
+        tampered.ext = meta0.ext.clone();
+        assert_equal!(
+            MetaMagic::from_enchanted(tampered.enchant()?, &magic),
+            Err(MSError::AuthenticationFailed)
+        );
+
+        // the mac itself was tampered with
+        let mut forged = enchanted.clone();
+        let mac = layout.field("mac").unwrap();
+        forged[mac.end() - 1] ^= 0x01;
+        assert_equal!(
+            MetaMagic::from_enchanted(forged, &magic),
+            Err(MSError::AuthenticationFailed)
+        );
+
+        // the mac was stripped from the header
+        let mut stripped = meta0.clone();
+        stripped.ext.remove(EXT_MAC);
+        assert_equal!(
+            MetaMagic::from_enchanted(stripped.enchant()?, &magic),
+            Err(MSError::AuthenticationFailed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_region() -> Result<(), MSError> {
+        let magic = "THISISMAGICO";
+        for (region, range) in [
+            (Region::Range(40, 8), (40, 8)),
+            (Region::Tail(22), (60, 22)),
+            (Region::Range(0, 82), (0, 82)),
+            (Region::Range(82, 0), (82, 0)),
+        ] {
+            let options = EnchantOptions {
+                region: Some(region),
+                ..EnchantOptions::default()
+            };
+            let meta0 = MetaMagic::with_options(test_data(), magic, &options)?;
+            assert_equal!(meta0.range()?, range);
+            assert_equal!(meta0.car(), reverse_slice(&test_data()[range.0..range.0 + range.1]));
+            assert_equal!(meta0.orig()?, test_data());
+
+            let enchanted = meta0.enchant()?;
+            assert_equal!(Layout::parse(&enchanted)?.get(&enchanted, "car").len(), range.1);
+            let meta1 = MetaMagic::from_enchanted(enchanted, magic)?;
+            assert_equal!(meta1.orig()?, test_data());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_region_out_of_bounds() {
+        let options = EnchantOptions {
+            region: Some(Region::Range(80, 8)),
+            ..EnchantOptions::default()
+        };
+        assert_equal!(
+            MetaMagic::with_options(test_data(), "THISISMAGICO", &options),
+            Err(MSError::OutOfBounds(88, 82))
+        );
+        assert_equal!(
+            MetaMagic::new(test_data()[..8].to_vec(), "THISISMAGICO"),
+            Err(MSError::OutOfBounds(CAR_SIZE, 8))
+        );
+    }
+
+    #[test]
+    fn test_metamagic_detached() -> Result<(), MSError> {
+        let magic = "THISISMAGICO";
+        for region in [None, Some(Region::Tail(10))] {
+            let options = EnchantOptions {
+                mac: true,
+                region,
+                ..EnchantOptions::default()
+            };
+            let meta0 = MetaMagic::with_options(test_data(), magic, &options)?;
+            let scrambled = meta0.scrambled()?;
+            assert_equal!(scrambled.len(), test_data().len());
+            assert_equal!(scrambled == test_data(), false);
+
+            let meta1 = MetaMagic::from_detached(&meta0.head()?, &scrambled, magic)?;
+            assert_equal!(meta1, meta0);
+            assert_equal!(meta1.orig()?, test_data());
+            assert_equal!(
+                MetaMagic::from_detached(&meta0.head()?, &scrambled[1..], magic).is_err(),
+                true
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_spells() -> Result<(), MSError> {
+        for spell in SpellKind::all() {
+            let options = EnchantOptions {
+                spell,
+                ..EnchantOptions::default()
+            };
+            // Given some bytes enchanted with the spell
+            let meta0 = MetaMagic::with_options(test_data(), "ALICE", &options)?;
+            assert_equal!(meta0.spell()?, spell);
+
+            // And keyed by a content key rather than by the magic in the clear
+            assert_equal!(meta0.ext.get(EXT_KEY_SLOTS).is_some(), spell.keyed());
+            assert_equal!(meta0.magic.is_empty(), spell.keyed());
+
+            // Then they should be restored picking the spell from the header
+            let meta1 = MetaMagic::from_enchanted(meta0.enchant()?, "ALICE")?;
+            assert_equal!(meta1.orig()?, test_data());
+            let detached = MetaMagic::from_detached(&meta0.head()?, &meta0.scrambled()?, "ALICE")?;
+            assert_equal!(detached.orig()?, test_data());
+
+            // And keep being restored after changing and adding magics
+            let mut meta2 = meta1.clone();
+            meta2.change_magic("ALICE", "CAROL")?;
+            let meta3 = MetaMagic::from_enchanted(meta2.enchant()?, "CAROL")?;
+            assert_equal!(meta3.orig()?, test_data());
+            meta2.add_magic("BOB")?;
+            let meta4 = MetaMagic::from_enchanted(meta2.enchant()?, "BOB")?;
+            assert_equal!(meta4.orig()?, test_data());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_key_slots() -> Result<(), MSError> {
+        let options = EnchantOptions {
+            mac: true,
+            recipients: vec!["BOB".to_string()],
+            ..EnchantOptions::default()
+        };
+        let meta0 = MetaMagic::with_options(test_data(), "ALICE", &options)?;
+        assert_equal!(meta0.key_slots(), 2);
+        assert_equal!(meta0.magic(), Vec::<u8>::new());
+        let enchanted = meta0.enchant()?;
+        assert_equal!(Layout::parse(&enchanted)?.get(&enchanted, "magic").len(), 0);
+
+        for magic in ["ALICE", "BOB"] {
+            let meta1 = MetaMagic::from_enchanted(enchanted.clone(), magic)?;
+            assert_equal!(meta1, meta0);
+            assert_equal!(meta1.orig()?, test_data());
+        }
+        assert_equal!(
+            MetaMagic::from_enchanted(enchanted.clone(), "EVE"),
+            Err(MSError::MagicMismatch)
+        );
+
+        // the body is kept as is when adding and removing magics
+        let mut meta2 = MetaMagic::from_enchanted(enchanted, "BOB")?;
+        meta2.add_magic("CAROL")?;
+        meta2.remove_magic("ALICE")?;
+        assert_equal!(meta2.key_slots(), 2);
+        assert_equal!(meta2.body(), meta0.body());
+        let rekeyed = meta2.enchant()?;
+        assert_equal!(MetaMagic::from_enchanted(rekeyed.clone(), "CAROL")?.orig()?, test_data());
+        assert_equal!(
+            MetaMagic::from_enchanted(rekeyed, "ALICE"),
+            Err(MSError::MagicMismatch)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_change_magic() -> Result<(), MSError> {
+        for recipients in [vec![], vec!["BOB".to_string()]] {
+            let options = EnchantOptions {
+                mac: true,
+                recipients,
+                ..EnchantOptions::default()
+            };
+            let mut meta = MetaMagic::with_options(test_data(), "ALICE", &options)?;
+            assert_equal!(meta.change_magic("EVE", "MALLORY"), Err(MSError::MagicMismatch));
+            meta.change_magic("ALICE", "CAROL")?;
+            let enchanted = meta.enchant()?;
+            assert_equal!(
+                MetaMagic::from_enchanted(enchanted.clone(), "ALICE"),
+                Err(MSError::MagicMismatch)
+            );
+            assert_equal!(MetaMagic::from_enchanted(enchanted, "CAROL")?.orig()?, test_data());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metamagic_add_magic_to_single_magic() -> Result<(), MSError> {
+        let mut meta = MetaMagic::new(test_data(), "ALICE")?;
+        assert_equal!(meta.key_slots(), 1);
+        assert_equal!(
+            meta.remove_magic("ALICE"),
+            Err(MSError::KeySlotError("the file has no key slots".to_string()))
+        );
+        meta.add_magic("BOB")?;
+        assert_equal!(meta.key_slots(), 2);
+        let enchanted = meta.enchant()?;
+        assert_equal!(MetaMagic::from_enchanted(enchanted.clone(), "ALICE")?.orig()?, test_data());
+        assert_equal!(MetaMagic::from_enchanted(enchanted, "BOB")?.orig()?, test_data());
+        assert_equal!(
+            meta.remove_magic("BOB").and_then(|_| meta.remove_magic("ALICE")),
+            Err(MSError::KeySlotError("cannot remove the last key slot".to_string()))
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_metamagic_restore() -> Result<(), MSError> {
         let magic = String::from("THISISMAGICO");
@@ -434,9 +1033,18 @@ mod tests {
         assert_equal!(meta0.magic_size, 12);
         assert_equal!(meta0.tail_size, 50);
 
-        let meta1 = MetaMagic::from_enchanted(enchanted, &magic.clone())?;
+        let meta1 = MetaMagic::from_enchanted(enchanted.clone(), &magic.clone())?;
         assert_equal!(meta0, meta1);
 
+        assert_equal!(
+            MetaMagic::from_enchanted(enchanted, "NOTTHEMAGICO"),
+            Err(MSError::MagicMismatch)
+        );
+        assert_equal!(
+            MetaMagic::from_enchanted(test_data(), &magic).is_err(),
+            true
+        );
+
         Ok(())
     }
 }
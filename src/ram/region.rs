@@ -0,0 +1,111 @@
+use crate::errors::MSError;
+use crate::p::str_to_usize;
+use crate::pad::unpad32;
+use crate::ram::ext::{Extensions, EXT_RANGE};
+use crate::ram::{usize_to_hex, CAR_SIZE, SIZE_SIZE};
+use std::str::FromStr;
+
+/// the part of the input moved into the car of the header, so that the
+/// bytes identifying the file are the ones scrambled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// `len` bytes from `start`, parsed from `START:LEN`
+    Range(usize, usize),
+    /// the last N bytes
+    Tail(usize),
+}
+
+impl Default for Region {
+    fn default() -> Region {
+        Region::Range(0, CAR_SIZE)
+    }
+}
+
+impl Region {
+    /// the start and length of the region within `size` bytes, failing
+    /// with `MSError::OutOfBounds` when it does not fit
+    pub fn resolve(&self, size: usize) -> Result<(usize, usize), MSError> {
+        let (start, len) = match *self {
+            Region::Range(start, len) => (start, len),
+            Region::Tail(len) => (size.saturating_sub(len), len),
+        };
+        if start.saturating_add(len) > size {
+            return Err(MSError::OutOfBounds(start.saturating_add(len), size));
+        }
+        Ok((start, len))
+    }
+}
+
+impl FromStr for Region {
+    type Err = MSError;
+
+    fn from_str(s: &str) -> Result<Region, MSError> {
+        let (start, len) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(MSError::InvalidLiteral(format!("expected START:LEN but found {s}"))),
+        };
+        Ok(Region::Range(str_to_usize(start)?, str_to_usize(len)?))
+    }
+}
+
+pub fn encode_range(start: usize, len: usize) -> Result<Vec<u8>, MSError> {
+    let mut value = usize_to_hex(start)?;
+    value.extend(usize_to_hex(len)?);
+    Ok(value)
+}
+
+pub fn decode_range(value: &[u8]) -> Result<(usize, usize), MSError> {
+    if value.len() != 2 * SIZE_SIZE {
+        return Err(MSError::InvalidHeader(format!(
+            "range needs {} bytes but has {}",
+            2 * SIZE_SIZE,
+            value.len()
+        )));
+    }
+    let start = unpad32(value[..SIZE_SIZE].to_vec())[0] as usize;
+    let len = unpad32(value[SIZE_SIZE..].to_vec())[0] as usize;
+    Ok((start, len))
+}
+
+/// the start and length of the car recorded in the extensions of an
+/// enchanted header, the first `CAR_SIZE` bytes when none was recorded
+pub fn recorded_range(ext: &Extensions) -> Result<(usize, usize), MSError> {
+    match ext.get(EXT_RANGE) {
+        Some(value) => decode_range(&value),
+        None => Ok((0, CAR_SIZE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_region_from_str() -> Result<(), MSError> {
+        assert_equal!("257:8".parse::<Region>()?, Region::Range(257, 8));
+        assert_equal!("0x101:0x8".parse::<Region>()?, Region::Range(257, 8));
+        assert_equal!("257".parse::<Region>().is_err(), true);
+        assert_equal!("a:8".parse::<Region>().is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_region_resolve() -> Result<(), MSError> {
+        assert_equal!(Region::default().resolve(100)?, (0, CAR_SIZE));
+        assert_equal!(Region::Range(10, 5).resolve(15)?, (10, 5));
+        assert_equal!(Region::Tail(22).resolve(100)?, (78, 22));
+        assert_equal!(Region::Range(10, 6).resolve(15), Err(MSError::OutOfBounds(16, 15)));
+        assert_equal!(Region::Tail(16).resolve(15), Err(MSError::OutOfBounds(16, 15)));
+        assert_equal!(Region::default().resolve(8), Err(MSError::OutOfBounds(CAR_SIZE, 8)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_roundtrip() -> Result<(), MSError> {
+        assert_equal!(hex::encode(encode_range(257, 8)?), "0000010100000008");
+        assert_equal!(decode_range(&encode_range(257, 8)?)?, (257, 8));
+        assert_equal!(decode_range(&[0x00]).is_err(), true);
+        Ok(())
+    }
+}
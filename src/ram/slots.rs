@@ -0,0 +1,124 @@
+use crate::errors::MSError;
+use crate::ram::mac::{hmac, verify_hmac, MAC_SIZE};
+
+/// size of the per-file content key wrapped by each slot
+pub const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+/// each slot is encoded as [salt][check][wrapped key]
+pub const SLOT_SIZE: usize = SALT_SIZE + MAC_SIZE + KEY_SIZE;
+
+pub fn random_bytes(size: usize) -> Result<Vec<u8>, MSError> {
+    let mut bytes = vec![0u8; size];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(()) => Ok(bytes),
+        Err(e) => Err(MSError::IOError(format!("failed to gather randomness: {e}"))),
+    }
+}
+
+/// a new random content key
+pub fn content_key() -> Result<Vec<u8>, MSError> {
+    random_bytes(KEY_SIZE)
+}
+
+fn derive(magic: &[u8], salt: &[u8], purpose: &[u8]) -> Vec<u8> {
+    hmac(magic, &[salt, purpose].concat())
+}
+
+/// whether `check` was derived from `magic`, compared in constant time
+fn verify(magic: &[u8], salt: &[u8], purpose: &[u8], check: &[u8]) -> bool {
+    verify_hmac(magic, &[salt, purpose].concat(), check)
+}
+
+fn xor(left: &[u8], right: &[u8]) -> Vec<u8> {
+    left.iter().zip(right).map(|(l, r)| l ^ r).collect()
+}
+
+/// what the check of a slot is derived for: slots of files that must
+/// carry a mac are told apart so that the mac cannot be stripped from
+/// the header without the magic
+fn check_purpose(mac: bool) -> &'static [u8] {
+    if mac {
+        b"check-mac"
+    } else {
+        b"check"
+    }
+}
+
+/// wraps `key` so that only `magic` can unwrap it, recording whether the
+/// file must carry a mac
+pub fn wrap(key: &[u8], magic: &[u8], mac: bool) -> Result<Vec<u8>, MSError> {
+    let salt = random_bytes(SALT_SIZE)?;
+    let mut slot = salt.clone();
+    slot.extend(derive(magic, &salt, check_purpose(mac)));
+    slot.extend(xor(key, &derive(magic, &salt, b"wrap")));
+    Ok(slot)
+}
+
+/// the key wrapped in `slot`, if it was wrapped with `magic`, and whether
+/// the file must carry a mac
+pub fn unwrap(slot: &[u8], magic: &[u8]) -> Option<(Vec<u8>, bool)> {
+    if slot.len() != SLOT_SIZE {
+        return None;
+    }
+    let (salt, rest) = slot.split_at(SALT_SIZE);
+    let (check, wrapped) = rest.split_at(MAC_SIZE);
+    let mac = [false, true]
+        .into_iter()
+        .find(|mac| verify(magic, salt, check_purpose(*mac), check))?;
+    Some((xor(wrapped, &derive(magic, salt, b"wrap")), mac))
+}
+
+/// splits the value of a key slots record into its slots, failing with
+/// `MSError::InvalidHeader` when it is not a whole number of slots
+pub fn slots(value: &[u8]) -> Result<Vec<&[u8]>, MSError> {
+    if value.is_empty() || !value.chunks_exact(SLOT_SIZE).remainder().is_empty() {
+        return Err(MSError::InvalidHeader(format!(
+            "key slots need a multiple of {SLOT_SIZE} bytes but have {}",
+            value.len()
+        )));
+    }
+    Ok(value.chunks(SLOT_SIZE).collect())
+}
+
+/// the index of the slot `magic` unwraps, the content key in it and
+/// whether the file must carry a mac
+pub fn unlock(value: &[u8], magic: &[u8]) -> Result<Option<(usize, Vec<u8>, bool)>, MSError> {
+    for (index, slot) in slots(value)?.into_iter().enumerate() {
+        if let Some((key, mac)) = unwrap(slot, magic) {
+            return Ok(Some((index, key, mac)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_wrap_unwrap() -> Result<(), MSError> {
+        let key = content_key()?;
+        let slot = wrap(&key, b"THISISMAGICO", false)?;
+        assert_equal!(slot.len(), SLOT_SIZE);
+        assert_equal!(unwrap(&slot, b"THISISMAGICO"), Some((key.clone(), false)));
+        assert_equal!(unwrap(&slot, b"NOTTHEMAGICO"), None);
+        // salted, so wrapping twice never yields the same slot
+        assert_equal!(wrap(&key, b"THISISMAGICO", false)? == slot, false);
+        // and slots of files carrying a mac say so
+        assert_equal!(unwrap(&wrap(&key, b"THISISMAGICO", true)?, b"THISISMAGICO"), Some((key, true)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unlock() -> Result<(), MSError> {
+        let key = content_key()?;
+        let mut value = wrap(&key, b"ALICE", false)?;
+        value.extend(wrap(&key, b"BOB", false)?);
+        assert_equal!(unlock(&value, b"ALICE")?, Some((0, key.clone(), false)));
+        assert_equal!(unlock(&value, b"BOB")?, Some((1, key.clone(), false)));
+        assert_equal!(unlock(&value, b"EVE")?, None);
+        assert_equal!(unlock(&value[1..], b"BOB").is_err(), true);
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use crate::errors::MSError;
+use crate::ram::ext::{Extensions, EXT_SPELL};
+use crate::ram::{hmac, reverse_slice};
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// how many bits `RotateBits` rotates each byte to the left
+pub const ROTATE_BITS: u32 = 3;
+/// size of the blocks moved around by `BlockShuffle`
+pub const SHUFFLE_BLOCK_SIZE: usize = 4;
+
+/// scrambles the car and the cdr of enchanted files; `key` is the content
+/// key of files with key slots, which keyed spells always create, else
+/// the magic. Spells hide signatures, they are no encryption: a repeated
+/// xor or a block order is easily undone without the key
+pub trait Spell {
+    fn apply(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError>;
+    /// the inverse of `apply` with the same key
+    fn revert(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError>;
+}
+
+/// fails with `MSError::MissingKey` for keyed spells given no key, which
+/// would leave the bytes as they were or order them predictably
+fn require_key(spell: &str, key: &[u8]) -> Result<(), MSError> {
+    if key.is_empty() {
+        return Err(MSError::MissingKey(spell.to_string()));
+    }
+    Ok(())
+}
+
+/// reverses the order of the bytes
+pub struct Reverse;
+/// xors the bytes with the key, repeated as needed
+pub struct XorKey;
+/// swaps the high and low nibbles of each byte
+pub struct NibbleSwap;
+/// rotates the bits of each byte `ROTATE_BITS` to the left
+pub struct RotateBits;
+/// permutes blocks of `SHUFFLE_BLOCK_SIZE` bytes in an order derived from
+/// the key, leaving a trailing partial block in place
+pub struct BlockShuffle;
+
+impl Spell for Reverse {
+    fn apply(&self, data: &[u8], _key: &[u8]) -> Result<Vec<u8>, MSError> {
+        Ok(reverse_slice(data))
+    }
+    fn revert(&self, data: &[u8], _key: &[u8]) -> Result<Vec<u8>, MSError> {
+        Ok(reverse_slice(data))
+    }
+}
+
+impl Spell for XorKey {
+    fn apply(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError> {
+        require_key("xor", key)?;
+        Ok(data.iter().zip(key.iter().cycle()).map(|(byte, k)| byte ^ k).collect())
+    }
+    fn revert(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError> {
+        self.apply(data, key)
+    }
+}
+
+impl Spell for NibbleSwap {
+    fn apply(&self, data: &[u8], _key: &[u8]) -> Result<Vec<u8>, MSError> {
+        Ok(data.iter().map(|byte| byte.rotate_left(4)).collect())
+    }
+    fn revert(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError> {
+        self.apply(data, key)
+    }
+}
+
+impl Spell for RotateBits {
+    fn apply(&self, data: &[u8], _key: &[u8]) -> Result<Vec<u8>, MSError> {
+        Ok(data.iter().map(|byte| byte.rotate_left(ROTATE_BITS)).collect())
+    }
+    fn revert(&self, data: &[u8], _key: &[u8]) -> Result<Vec<u8>, MSError> {
+        Ok(data.iter().map(|byte| byte.rotate_right(ROTATE_BITS)).collect())
+    }
+}
+
+/// `len` pseudo-random bytes derived from `key`
+fn keystream(key: &[u8], len: usize) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(len);
+    let mut counter: u64 = 0;
+    while stream.len() < len {
+        stream.extend(hmac(key, &counter.to_be_bytes()));
+        counter += 1;
+    }
+    stream.truncate(len);
+    stream
+}
+
+/// the order of `blocks` blocks, `order[i]` being the index of the
+/// original block moved to `i`, shuffled by Fisher-Yates
+fn shuffled_order(key: &[u8], blocks: usize) -> Vec<usize> {
+    let stream = keystream(key, blocks * 4);
+    let mut order: Vec<usize> = (0..blocks).collect();
+    for i in (1..blocks).rev() {
+        let random = u32::from_be_bytes([stream[i * 4], stream[i * 4 + 1], stream[i * 4 + 2], stream[i * 4 + 3]]);
+        order.swap(i, random as usize % (i + 1));
+    }
+    order
+}
+
+impl Spell for BlockShuffle {
+    fn apply(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError> {
+        require_key("shuffle", key)?;
+        let blocks: Vec<&[u8]> = data.chunks_exact(SHUFFLE_BLOCK_SIZE).collect();
+        let mut output = Vec::with_capacity(data.len());
+        for index in shuffled_order(key, blocks.len()) {
+            output.extend(blocks[index]);
+        }
+        output.extend(data.chunks_exact(SHUFFLE_BLOCK_SIZE).remainder());
+        Ok(output)
+    }
+    fn revert(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError> {
+        require_key("shuffle", key)?;
+        let mut output = data.to_vec();
+        for (position, index) in shuffled_order(key, data.len() / SHUFFLE_BLOCK_SIZE).into_iter().enumerate() {
+            let block = &data[position * SHUFFLE_BLOCK_SIZE..(position + 1) * SHUFFLE_BLOCK_SIZE];
+            output[index * SHUFFLE_BLOCK_SIZE..(index + 1) * SHUFFLE_BLOCK_SIZE].copy_from_slice(block);
+        }
+        Ok(output)
+    }
+}
+
+/// the built-in spells; the discriminants are stored in the header so
+/// they must never change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[repr(u8)]
+pub enum SpellKind {
+    /// reverses the bytes, the only spell of headers without a spell record
+    #[default]
+    Reverse = 0,
+    /// xors the bytes with the content key, see `SpellKind::keyed`
+    Xor = 1,
+    /// swaps the nibbles of each byte
+    Nibbles = 2,
+    /// rotates the bits of each byte
+    Rotate = 3,
+    /// shuffles blocks of bytes in an order derived from the content key
+    Shuffle = 4,
+}
+
+impl SpellKind {
+    pub fn all() -> Vec<SpellKind> {
+        vec![
+            SpellKind::Reverse,
+            SpellKind::Xor,
+            SpellKind::Nibbles,
+            SpellKind::Rotate,
+            SpellKind::Shuffle,
+        ]
+    }
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_id(id: u8) -> Result<SpellKind, MSError> {
+        match SpellKind::all().into_iter().find(|s| s.id() == id) {
+            Some(spell) => Ok(spell),
+            None => Err(MSError::InvalidHeader(format!("unknown spell: {id}"))),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpellKind::Reverse => "reverse",
+            SpellKind::Xor => "xor",
+            SpellKind::Nibbles => "nibbles",
+            SpellKind::Rotate => "rotate",
+            SpellKind::Shuffle => "shuffle",
+        }
+    }
+    /// whether the scrambled bytes depend on the key: enchanting with a
+    /// keyed spell keeps the magic in a key slot wrapping a content key,
+    /// rather than in the clear next to the bytes it keys
+    pub fn keyed(&self) -> bool {
+        matches!(self, SpellKind::Xor | SpellKind::Shuffle)
+    }
+    pub fn spell(&self) -> &'static dyn Spell {
+        match self {
+            SpellKind::Reverse => &Reverse,
+            SpellKind::Xor => &XorKey,
+            SpellKind::Nibbles => &NibbleSwap,
+            SpellKind::Rotate => &RotateBits,
+            SpellKind::Shuffle => &BlockShuffle,
+        }
+    }
+}
+
+/// the spell recorded in the extensions of a header, reversal if none
+pub fn recorded_spell(ext: &Extensions) -> Result<SpellKind, MSError> {
+    match ext.get(EXT_SPELL) {
+        Some(id) => SpellKind::from_id(*id.first().unwrap_or(&0xff)),
+        None => Ok(SpellKind::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    #[test]
+    fn test_spells_revert_what_they_apply() -> Result<(), MSError> {
+        let data: Vec<u8> = (0u8..=255).cycle().take(1001).collect();
+        for kind in SpellKind::all() {
+            assert_equal!(SpellKind::from_id(kind.id())?, kind);
+            let spell = kind.spell();
+            let scrambled = spell.apply(&data, b"THISISMAGICO")?;
+            assert_equal!(scrambled.len(), data.len());
+            assert_equal!(scrambled != data, true);
+            assert_equal!(spell.revert(&scrambled, b"THISISMAGICO")?, data);
+            if kind.keyed() {
+                assert_equal!(spell.revert(&scrambled, b"NOTTHEMAGICO")? != data, true);
+                assert_equal!(spell.apply(&data, b""), Err(MSError::MissingKey(kind.name().to_string())));
+                assert_equal!(spell.revert(&scrambled, b""), Err(MSError::MissingKey(kind.name().to_string())));
+            }
+        }
+        assert_equal!(SpellKind::from_id(0xff).is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_spells() -> Result<(), MSError> {
+        assert_equal!(Reverse.apply(&[0x01, 0x02, 0x03], b"")?, vec![0x03, 0x02, 0x01]);
+        assert_equal!(XorKey.apply(&[0x01, 0x02, 0x03], &[0xff, 0x00])?, vec![0xfe, 0x02, 0xfc]);
+        assert_equal!(NibbleSwap.apply(&[0x12, 0xab], b"")?, vec![0x21, 0xba]);
+        assert_equal!(RotateBits.apply(&[0x01, 0x80], b"")?, vec![0x08, 0x04]);
+        assert_equal!(BlockShuffle.apply(&[0x01, 0x02], b"KEY")?, vec![0x01, 0x02]);
+        Ok(())
+    }
+}
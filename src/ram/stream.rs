@@ -0,0 +1,154 @@
+use crate::errors::MSError;
+use crate::ram::Enchanter;
+use std::io::{self, Read, Write};
+
+// the header holds digests of the whole input, so neither adapter can emit
+// anything before it has seen all of its input: both buffer it in memory
+
+/// accepts the original bytes and writes them enchanted to `inner` once
+/// finished, either by `finish` or, ignoring errors, when dropped
+#[derive(Debug)]
+pub struct EnchantWriter<W: Write> {
+    inner: Option<W>,
+    enchanter: Enchanter,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EnchantWriter<W> {
+    pub fn new(inner: W, enchanter: Enchanter) -> EnchantWriter<W> {
+        EnchantWriter {
+            inner: Some(inner),
+            enchanter,
+            buffer: Vec::new(),
+        }
+    }
+    /// writes the enchanted bytes once, taking the sink they were written to
+    fn try_finish(&mut self) -> Result<Option<W>, MSError> {
+        match self.inner.take() {
+            Some(mut inner) => {
+                inner.write_all(&self.enchanter.enchant(&self.buffer)?)?;
+                inner.flush()?;
+                Ok(Some(inner))
+            }
+            None => Ok(None),
+        }
+    }
+    /// writes the enchanted bytes, returning the sink they were written to
+    pub fn finish(mut self) -> Result<W, MSError> {
+        match self.try_finish()? {
+            Some(inner) => Ok(inner),
+            None => unreachable!("only finish and drop take the sink"),
+        }
+    }
+}
+
+impl<W: Write> Write for EnchantWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    /// nothing can be written before `finish`, see `EnchantWriter`
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for EnchantWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+    }
+}
+
+/// reads an enchanted stream from `inner`, yielding its original bytes
+#[derive(Debug)]
+pub struct RestoreReader<R: Read> {
+    inner: R,
+    enchanter: Enchanter,
+    restored: Option<io::Cursor<Vec<u8>>>,
+}
+
+impl<R: Read> RestoreReader<R> {
+    pub fn new(inner: R, enchanter: Enchanter) -> RestoreReader<R> {
+        RestoreReader {
+            inner,
+            enchanter,
+            restored: None,
+        }
+    }
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for RestoreReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.restored.is_none() {
+            let mut enchanted = Vec::new();
+            self.inner.read_to_end(&mut enchanted)?;
+            self.restored = Some(io::Cursor::new(self.enchanter.restore(&enchanted)?));
+        }
+        match self.restored.as_mut() {
+            Some(restored) => restored.read(buf),
+            None => unreachable!("restored above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram::MetaMagic;
+    use k9::assert_equal;
+
+    fn test_data() -> Vec<u8> {
+        (0u8..=255).cycle().take(1000).collect()
+    }
+
+    #[test]
+    fn test_enchant_writer_and_restore_reader() -> Result<(), MSError> {
+        // Given an enchant writer over a buffer
+        let mut writer = EnchantWriter::new(Vec::new(), Enchanter::new("THISISMAGICO"));
+
+        // When I write some bytes in chunks and finish
+        for chunk in test_data().chunks(100) {
+            writer.write_all(chunk)?;
+        }
+        let enchanted = writer.finish()?;
+
+        // Then the buffer should hold them enchanted
+        assert_equal!(
+            MetaMagic::from_enchanted(enchanted.clone(), "THISISMAGICO")?.orig()?,
+            test_data()
+        );
+
+        // And a restore reader should yield them back
+        let mut reader = RestoreReader::new(enchanted.as_slice(), Enchanter::new("THISISMAGICO"));
+        let mut restored = Vec::new();
+        reader.read_to_end(&mut restored)?;
+        assert_equal!(restored, test_data());
+        Ok(())
+    }
+
+    #[test]
+    fn test_enchant_writer_finishes_when_dropped() -> Result<(), MSError> {
+        let mut enchanted = Vec::new();
+        {
+            let mut writer = EnchantWriter::new(&mut enchanted, Enchanter::new("THISISMAGICO"));
+            writer.write_all(&test_data())?;
+        }
+        assert_equal!(
+            MetaMagic::from_enchanted(enchanted, "THISISMAGICO")?.orig()?,
+            test_data()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_reader_with_wrong_magic() -> Result<(), MSError> {
+        let enchanted = Enchanter::new("THISISMAGICO").enchant(&test_data())?;
+        let mut reader = RestoreReader::new(enchanted.as_slice(), Enchanter::new("NOTTHEMAGICO"));
+        let error = reader.read_to_end(&mut Vec::new()).err().map(|e| e.kind());
+        assert_equal!(error, Some(io::ErrorKind::InvalidData));
+        Ok(())
+    }
+}
@@ -55,7 +55,7 @@ pub fn rsubnseq(origin: &[u8], matchsq: &[u8], replcsq: &[u8], occrs: usize) ->
                 break;
             }
         };
-        srcco = srcco + 1;
+        srcco += 1;
     }
     if srcco < occrs {
         return Err(VecsException::NotAllOccurrencesReplaced((matchsq.to_vec(), srcco, occrs)))
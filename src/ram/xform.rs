@@ -0,0 +1,238 @@
+use crate::errors::MSError;
+use crate::p::{str_to_bytes, str_to_usize};
+use crate::ram::{BlockShuffle, NibbleSwap, Reverse, RotateBits, Spell, XorKey};
+use std::str::FromStr;
+
+/// swaps the order of the bytes within each word of `self.0` bytes,
+/// leaving a trailing partial word in place
+pub struct SwapWords(pub usize);
+
+impl Spell for SwapWords {
+    fn apply(&self, data: &[u8], _key: &[u8]) -> Result<Vec<u8>, MSError> {
+        let mut output = Vec::with_capacity(data.len());
+        for word in data.chunks_exact(self.0) {
+            output.extend(word.iter().rev());
+        }
+        output.extend(data.chunks_exact(self.0).remainder());
+        Ok(output)
+    }
+    fn revert(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, MSError> {
+        self.apply(data, key)
+    }
+}
+
+/// the reversible transforms of `ms xform`, the keyed ones taking their
+/// key as argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformKind {
+    Reverse,
+    Xor,
+    Nibbles,
+    Rotate,
+    Swap16,
+    Swap32,
+    Shuffle,
+}
+
+impl TransformKind {
+    pub fn all() -> Vec<TransformKind> {
+        vec![
+            TransformKind::Reverse,
+            TransformKind::Xor,
+            TransformKind::Nibbles,
+            TransformKind::Rotate,
+            TransformKind::Swap16,
+            TransformKind::Swap32,
+            TransformKind::Shuffle,
+        ]
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransformKind::Reverse => "reverse",
+            TransformKind::Xor => "xor",
+            TransformKind::Nibbles => "nibbles",
+            TransformKind::Rotate => "rotate",
+            TransformKind::Swap16 => "swap16",
+            TransformKind::Swap32 => "swap32",
+            TransformKind::Shuffle => "shuffle",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<TransformKind> {
+        TransformKind::all().into_iter().find(|kind| kind.name() == name)
+    }
+    pub fn keyed(&self) -> bool {
+        matches!(self, TransformKind::Xor | TransformKind::Shuffle)
+    }
+    pub fn spell(&self) -> &'static dyn Spell {
+        match self {
+            TransformKind::Reverse => &Reverse,
+            TransformKind::Xor => &XorKey,
+            TransformKind::Nibbles => &NibbleSwap,
+            TransformKind::Rotate => &RotateBits,
+            TransformKind::Swap16 => &SwapWords(2),
+            TransformKind::Swap32 => &SwapWords(4),
+            TransformKind::Shuffle => &BlockShuffle,
+        }
+    }
+}
+
+/// a transform applied to the bytes from `start` up to `end`, parsed from
+/// `NAME[:KEY][:START..END]`, e.g.: `reverse:0..32`, `xor:ff:32..`, `swap16`;
+/// the key is hex, `0x` being optional, or any typed literal accepted by
+/// `gp`, e.g.: `s:"KEY"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transform {
+    pub kind: TransformKind,
+    pub key: Vec<u8>,
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+impl Transform {
+    /// the start and length of the transformed bytes within `size` bytes,
+    /// failing with `MSError::OutOfBounds` when they do not fit
+    pub fn resolve(&self, size: usize) -> Result<(usize, usize), MSError> {
+        let start = self.start.unwrap_or(0);
+        let end = self.end.unwrap_or(size);
+        if end > size || start > end {
+            return Err(MSError::OutOfBounds(end.max(start), size));
+        }
+        Ok((start, end - start))
+    }
+    fn run(&self, input: &mut [u8], invert: bool) -> Result<(), MSError> {
+        let (start, len) = self.resolve(input.len())?;
+        let region = &mut input[start..start + len];
+        let spell = self.kind.spell();
+        let output = if invert {
+            spell.revert(region, &self.key)?
+        } else {
+            spell.apply(region, &self.key)?
+        };
+        region.copy_from_slice(&output);
+        Ok(())
+    }
+}
+
+fn parse_offset(value: &str) -> Result<Option<usize>, MSError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(str_to_usize(value)?))
+}
+
+impl FromStr for Transform {
+    type Err = MSError;
+
+    fn from_str(s: &str) -> Result<Transform, MSError> {
+        let mut parts: Vec<&str> = s.split(':').collect();
+        let (start, end) = match parts.last().and_then(|last| last.split_once("..")) {
+            Some((start, end)) if parts.len() > 1 => {
+                let bounds = (parse_offset(start)?, parse_offset(end)?);
+                parts.pop();
+                bounds
+            }
+            _ => (None, None),
+        };
+        let kind = match TransformKind::from_name(parts[0]) {
+            Some(kind) => kind,
+            None => return Err(MSError::InvalidLiteral(format!("unknown transform: {}", parts[0]))),
+        };
+        let key = match (kind.keyed(), parts[1..].join(":")) {
+            (true, key) if key.is_empty() => {
+                return Err(MSError::InvalidLiteral(format!("{} needs a key, e.g.: {}:ff", kind.name(), kind.name())))
+            }
+            (true, key) if key.contains(':') => str_to_bytes(&key)?,
+            (true, key) => hex::decode(key.strip_prefix("0x").unwrap_or(&key))?,
+            (false, key) if key.is_empty() => Vec::new(),
+            (false, key) => {
+                return Err(MSError::InvalidLiteral(format!("{} takes no key but was given {key}", kind.name())))
+            }
+        };
+        Ok(Transform { kind, key, start, end })
+    }
+}
+
+/// `input` with each transform applied in order or, when `invert`, with
+/// each transform reverted in the reverse order
+pub fn xform(input: &[u8], transforms: &[Transform], invert: bool) -> Result<Vec<u8>, MSError> {
+    let mut output = input.to_vec();
+    if invert {
+        for transform in transforms.iter().rev() {
+            transform.run(&mut output, true)?;
+        }
+    } else {
+        for transform in transforms {
+            transform.run(&mut output, false)?;
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k9::assert_equal;
+
+    fn transforms(ops: &[&str]) -> Result<Vec<Transform>, MSError> {
+        ops.iter().map(|op| op.parse()).collect()
+    }
+
+    #[test]
+    fn test_parse_transform() -> Result<(), MSError> {
+        assert_equal!(
+            "reverse:0..32".parse::<Transform>()?,
+            Transform { kind: TransformKind::Reverse, key: vec![], start: Some(0), end: Some(32) }
+        );
+        assert_equal!(
+            "xor:ff:0x20..".parse::<Transform>()?,
+            Transform { kind: TransformKind::Xor, key: vec![0xff], start: Some(32), end: None }
+        );
+        assert_equal!(
+            "xor:ff:32..".parse::<Transform>()?,
+            Transform { kind: TransformKind::Xor, key: vec![0xff], start: Some(32), end: None }
+        );
+        assert_equal!("xor:10".parse::<Transform>()?.key, vec![0x10]);
+        assert_equal!("xor:0xff".parse::<Transform>()?.key, vec![0xff]);
+        assert_equal!("xor:h:ff00".parse::<Transform>()?.key, vec![0xff, 0x00]);
+        assert_equal!("xor:u16le:1".parse::<Transform>()?.key, vec![0x01, 0x00]);
+        assert_equal!(
+            "shuffle:s:\"KEY\"".parse::<Transform>()?,
+            Transform { kind: TransformKind::Shuffle, key: b"KEY".to_vec(), start: None, end: None }
+        );
+        assert_equal!("swap16".parse::<Transform>()?.kind, TransformKind::Swap16);
+        assert_equal!("xor".parse::<Transform>().is_err(), true);
+        assert_equal!("xor:zz".parse::<Transform>().is_err(), true);
+        assert_equal!("reverse:ff".parse::<Transform>().is_err(), true);
+        assert_equal!("unknown:0..2".parse::<Transform>().is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xform() -> Result<(), MSError> {
+        let data: Vec<u8> = (0u8..8).collect();
+        assert_equal!(
+            xform(&data, &transforms(&["reverse:0..4"])?, false)?,
+            vec![3, 2, 1, 0, 4, 5, 6, 7]
+        );
+        assert_equal!(
+            xform(&data, &transforms(&["swap16", "xor:ff:6.."])?, false)?,
+            vec![1, 0, 3, 2, 5, 4, 0xf8, 0xf9]
+        );
+        assert_equal!(
+            xform(&data, &transforms(&["reverse:..3", "swap32:1.."])?, false)?,
+            vec![2, 4, 3, 0, 1, 5, 6, 7]
+        );
+        assert_equal!(xform(&data, &transforms(&["reverse:4..9"])?, false).is_err(), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xform_invert() -> Result<(), MSError> {
+        let data: Vec<u8> = (0u8..=255).collect();
+        let chain = transforms(&["reverse:0..32", "xor:ff:32..", "swap16", "rotate:3..200", "shuffle:abcd", "nibbles"])?;
+        let transformed = xform(&data, &chain, false)?;
+        assert_equal!(transformed != data, true);
+        assert_equal!(xform(&transformed, &chain, true)?, data);
+        Ok(())
+    }
+}
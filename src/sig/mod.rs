@@ -0,0 +1,289 @@
+use crate::ram::{recorded_spell, Layout, EXT_FILE_TYPE};
+use std::fmt;
+
+/// the discriminants are stored in the header of enchanted files,
+/// so they must never change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FileType {
+    Png = 1,
+    Jpeg = 2,
+    Gif = 3,
+    Zip = 4,
+    Pdf = 5,
+    Elf = 6,
+    Gzip = 7,
+    Xz = 8,
+    Bzip2 = 9,
+    SevenZip = 10,
+    Zstd = 11,
+    Rar = 12,
+    Tar = 13,
+    Bmp = 14,
+    Tiff = 15,
+    Ico = 16,
+    Webp = 17,
+    Wav = 18,
+    Ogg = 19,
+    Flac = 20,
+    Mp3 = 21,
+    Sqlite = 22,
+    Wasm = 23,
+    Pe = 24,
+    MachO = 25,
+    Class = 26,
+}
+
+/// bytes expected at given offsets, all of which must match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signature {
+    pub file_type: FileType,
+    pub parts: &'static [(usize, &'static [u8])],
+}
+
+impl Signature {
+    pub fn matches(&self, input: &[u8]) -> bool {
+        self.parts
+            .iter()
+            .all(|(offset, bytes)| input.get(*offset..*offset + bytes.len()) == Some(*bytes))
+    }
+    /// the number of bytes from the start of a file covered by this signature
+    pub fn span(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|(offset, bytes)| offset + bytes.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// known signatures, the more specific ones first
+pub const SIGNATURES: &[Signature] = &[
+    Signature { file_type: FileType::Png, parts: &[(0, b"\x89PNG\r\n\x1a\n")] },
+    Signature { file_type: FileType::Jpeg, parts: &[(0, b"\xff\xd8\xff")] },
+    Signature { file_type: FileType::Gif, parts: &[(0, b"GIF87a")] },
+    Signature { file_type: FileType::Gif, parts: &[(0, b"GIF89a")] },
+    Signature { file_type: FileType::Zip, parts: &[(0, b"PK\x03\x04")] },
+    Signature { file_type: FileType::Zip, parts: &[(0, b"PK\x05\x06")] },
+    Signature { file_type: FileType::Zip, parts: &[(0, b"PK\x07\x08")] },
+    Signature { file_type: FileType::Pdf, parts: &[(0, b"%PDF-")] },
+    Signature { file_type: FileType::Elf, parts: &[(0, b"\x7fELF")] },
+    Signature { file_type: FileType::Gzip, parts: &[(0, b"\x1f\x8b")] },
+    Signature { file_type: FileType::Xz, parts: &[(0, b"\xfd7zXZ\x00")] },
+    Signature { file_type: FileType::Bzip2, parts: &[(0, b"BZh")] },
+    Signature { file_type: FileType::SevenZip, parts: &[(0, b"7z\xbc\xaf\x27\x1c")] },
+    Signature { file_type: FileType::Zstd, parts: &[(0, b"\x28\xb5\x2f\xfd")] },
+    Signature { file_type: FileType::Rar, parts: &[(0, b"Rar!\x1a\x07")] },
+    Signature { file_type: FileType::Tar, parts: &[(257, b"ustar")] },
+    Signature { file_type: FileType::Webp, parts: &[(0, b"RIFF"), (8, b"WEBP")] },
+    Signature { file_type: FileType::Wav, parts: &[(0, b"RIFF"), (8, b"WAVE")] },
+    Signature { file_type: FileType::Tiff, parts: &[(0, b"II\x2a\x00")] },
+    Signature { file_type: FileType::Tiff, parts: &[(0, b"MM\x00\x2a")] },
+    Signature { file_type: FileType::Ico, parts: &[(0, b"\x00\x00\x01\x00")] },
+    Signature { file_type: FileType::Ogg, parts: &[(0, b"OggS")] },
+    Signature { file_type: FileType::Flac, parts: &[(0, b"fLaC")] },
+    Signature { file_type: FileType::Mp3, parts: &[(0, b"ID3")] },
+    Signature { file_type: FileType::Sqlite, parts: &[(0, b"SQLite format 3\x00")] },
+    Signature { file_type: FileType::Wasm, parts: &[(0, b"\x00asm")] },
+    Signature { file_type: FileType::MachO, parts: &[(0, b"\xfe\xed\xfa\xce")] },
+    Signature { file_type: FileType::MachO, parts: &[(0, b"\xfe\xed\xfa\xcf")] },
+    Signature { file_type: FileType::MachO, parts: &[(0, b"\xce\xfa\xed\xfe")] },
+    Signature { file_type: FileType::MachO, parts: &[(0, b"\xcf\xfa\xed\xfe")] },
+    Signature { file_type: FileType::Class, parts: &[(0, b"\xca\xfe\xba\xbe")] },
+    Signature { file_type: FileType::Bmp, parts: &[(0, b"BM")] },
+    Signature { file_type: FileType::Pe, parts: &[(0, b"MZ")] },
+];
+
+impl FileType {
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_id(id: u8) -> Option<FileType> {
+        FileType::all().into_iter().find(|t| t.id() == id)
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::Png => "png",
+            FileType::Jpeg => "jpeg",
+            FileType::Gif => "gif",
+            FileType::Zip => "zip",
+            FileType::Pdf => "pdf",
+            FileType::Elf => "elf",
+            FileType::Gzip => "gzip",
+            FileType::Xz => "xz",
+            FileType::Bzip2 => "bzip2",
+            FileType::SevenZip => "7z",
+            FileType::Zstd => "zstd",
+            FileType::Rar => "rar",
+            FileType::Tar => "tar",
+            FileType::Bmp => "bmp",
+            FileType::Tiff => "tiff",
+            FileType::Ico => "ico",
+            FileType::Webp => "webp",
+            FileType::Wav => "wav",
+            FileType::Ogg => "ogg",
+            FileType::Flac => "flac",
+            FileType::Mp3 => "mp3",
+            FileType::Sqlite => "sqlite",
+            FileType::Wasm => "wasm",
+            FileType::Pe => "pe",
+            FileType::MachO => "mach-o",
+            FileType::Class => "class",
+        }
+    }
+    pub fn description(&self) -> &'static str {
+        match self {
+            FileType::Png => "PNG image",
+            FileType::Jpeg => "JPEG image",
+            FileType::Gif => "GIF image",
+            FileType::Zip => "ZIP archive",
+            FileType::Pdf => "PDF document",
+            FileType::Elf => "ELF executable",
+            FileType::Gzip => "gzip compressed data",
+            FileType::Xz => "xz compressed data",
+            FileType::Bzip2 => "bzip2 compressed data",
+            FileType::SevenZip => "7-zip archive",
+            FileType::Zstd => "zstandard compressed data",
+            FileType::Rar => "RAR archive",
+            FileType::Tar => "tar archive",
+            FileType::Bmp => "BMP image",
+            FileType::Tiff => "TIFF image",
+            FileType::Ico => "Windows icon",
+            FileType::Webp => "WebP image",
+            FileType::Wav => "WAVE audio",
+            FileType::Ogg => "Ogg container",
+            FileType::Flac => "FLAC audio",
+            FileType::Mp3 => "MP3 audio with ID3 tag",
+            FileType::Sqlite => "SQLite 3 database",
+            FileType::Wasm => "WebAssembly module",
+            FileType::Pe => "DOS/Windows executable",
+            FileType::MachO => "Mach-O binary",
+            FileType::Class => "Java class file",
+        }
+    }
+    pub fn all() -> Vec<FileType> {
+        let mut all = Vec::<FileType>::new();
+        for signature in SIGNATURES {
+            if !all.contains(&signature.file_type) {
+                all.push(signature.file_type);
+            }
+        }
+        all
+    }
+    pub fn from_name(name: &str) -> Option<FileType> {
+        let name = name.to_lowercase();
+        FileType::all().into_iter().find(|t| match name.as_str() {
+            "jpg" => *t == FileType::Jpeg,
+            "tif" => *t == FileType::Tiff,
+            "exe" => *t == FileType::Pe,
+            "macho" => *t == FileType::MachO,
+            name => t.name() == name,
+        })
+    }
+    /// the first known signature of this type
+    pub fn signature(&self) -> Signature {
+        *SIGNATURES
+            .iter()
+            .find(|s| s.file_type == *self)
+            .expect("every file type has a signature")
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub fn detect_signature(input: &[u8]) -> Option<Signature> {
+    SIGNATURES.iter().find(|s| s.matches(input)).copied()
+}
+
+pub fn detect(input: &[u8]) -> Option<FileType> {
+    detect_signature(input).map(|s| s.file_type)
+}
+
+/// detects the type of an enchanted file from the type recorded in its
+/// header or else from its car, unless its spell needs the magic, without
+/// restoring it; `None` if `input` is not enchanted
+pub fn detect_enchanted(input: &[u8]) -> Option<Option<FileType>> {
+    let layout = Layout::parse(input).ok()?;
+    let ext = layout.extensions(input).ok()?;
+    let recorded = match ext.get(EXT_FILE_TYPE) {
+        Some(id) => id.first().and_then(|id| FileType::from_id(*id)),
+        None => None,
+    };
+    Some(recorded.or_else(|| match recorded_spell(&ext) {
+        Ok(spell) if !spell.keyed() => detect(&spell.spell().revert(layout.get(input, "car"), &[]).ok()?),
+        _ => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::MSError;
+    use crate::ram::MetaMagic;
+    use k9::assert_equal;
+
+    fn test_png() -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::from([
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52,
+        ]);
+        data.extend((0u8..50).collect::<Vec<u8>>());
+        data
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_equal!(detect(&test_png()), Some(FileType::Png));
+        assert_equal!(detect(b"\xff\xd8\xff\xe0\x00\x10JFIF"), Some(FileType::Jpeg));
+        assert_equal!(detect(b"GIF89a\x01\x00"), Some(FileType::Gif));
+        assert_equal!(detect(b"PK\x03\x04\x14\x00"), Some(FileType::Zip));
+        assert_equal!(detect(b"%PDF-1.7\n"), Some(FileType::Pdf));
+        assert_equal!(detect(b"\x7fELF\x02\x01\x01"), Some(FileType::Elf));
+        assert_equal!(detect(b"\x1f\x8b\x08\x00"), Some(FileType::Gzip));
+        assert_equal!(detect(b"\xfd7zXZ\x00\x00\x04"), Some(FileType::Xz));
+        assert_equal!(detect(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some(FileType::Webp));
+        assert_equal!(detect(b"RIFF\x00\x00\x00\x00WAVEfmt "), Some(FileType::Wav));
+        assert_equal!(detect(b"RIFF\x00\x00"), None);
+        assert_equal!(detect(b"plain text"), None);
+        assert_equal!(detect(&[]), None);
+    }
+
+    #[test]
+    fn test_detect_tar() {
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_equal!(detect(&tar), Some(FileType::Tar));
+        assert_equal!(FileType::Tar.signature().span(), 262);
+    }
+
+    #[test]
+    fn test_detect_enchanted() -> Result<(), MSError> {
+        let enchanted = MetaMagic::new(test_png(), "THISISMAGICO")?.enchant()?;
+        assert_equal!(detect(&enchanted), None);
+        assert_equal!(detect_enchanted(&enchanted), Some(Some(FileType::Png)));
+        assert_equal!(detect_enchanted(&test_png()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ids() {
+        assert_equal!(FileType::Png.id(), 1);
+        assert_equal!(FileType::from_id(FileType::Zip.id()), Some(FileType::Zip));
+        assert_equal!(FileType::from_id(0), None);
+        for file_type in FileType::all() {
+            assert_equal!(FileType::from_id(file_type.id()), Some(file_type));
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_equal!(FileType::from_name("ZIP"), Some(FileType::Zip));
+        assert_equal!(FileType::from_name("jpg"), Some(FileType::Jpeg));
+        assert_equal!(FileType::from_name("7z"), Some(FileType::SevenZip));
+        assert_equal!(FileType::from_name("nope"), None);
+    }
+}